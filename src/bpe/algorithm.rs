@@ -1,7 +1,7 @@
-﻿use super::{Bpe, utok};
+use super::{Bpe, utok};
 use std::{
     cmp::Ordering::{self, Equal},
-    collections::BinaryHeap,
+    collections::{BinaryHeap, HashSet},
     fmt,
     iter::zip,
     ops::Range,
@@ -12,6 +12,30 @@ pub struct MergeState<'v, 't> {
     bpe: &'v Bpe,
     marks: Vec<Mark>,
     merges: BinaryHeap<Merge>,
+    /// 只允许产生这些词，`None` 表示不限制
+    allowed: Option<&'v HashSet<utok>>,
+}
+
+/// 一次成功的合并
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MergeEvent {
+    /// 合并位置在文本中的字节偏移
+    pub pos: usize,
+    /// 左侧 token
+    pub left: utok,
+    /// 右侧 token
+    pub right: utok,
+    /// 合并产生的 token
+    pub merged: utok,
+    /// 合并产生的 token 的排名
+    pub rank: u32,
+}
+
+/// 编码过程的中间存储，在多次编码之间复用以避免重复分配内存
+#[derive(Default)]
+pub struct EncodeScratch {
+    marks: Vec<Mark>,
+    merges: BinaryHeap<Merge>,
 }
 
 pub struct IntoIter<'v> {
@@ -27,14 +51,55 @@ pub struct Iter<'a> {
 
 impl Bpe {
     pub fn begin_merge<'v, 't>(&'v self, text: &'t str) -> MergeState<'v, 't> {
-        let mut marks = vec![Mark::unk(self.unk); text.len()];
-        let mut merges = BinaryHeap::new();
+        self.begin_merge_in(text, Default::default(), None)
+    }
+
+    /// 开始合并，只使用 `allowed` 中的词，其他字符和单字节词视为 unk
+    pub(super) fn begin_merge_restricted<'v, 't>(
+        &'v self,
+        text: &'t str,
+        allowed: &'v HashSet<utok>,
+    ) -> MergeState<'v, 't> {
+        self.begin_merge_in(text, Default::default(), Some(allowed))
+    }
+
+    /// 使用 `scratch` 中的存储合并 `text`，结果追加到 `out`，之后归还存储
+    pub(super) fn merge_into(&self, text: &str, out: &mut Vec<utok>, scratch: &mut EncodeScratch) {
+        let mut state = self.begin_merge_in(text, std::mem::take(scratch), None);
+        // 每次合并减少一个 token，正常情况下合并次数少于文本的字节数
+        if !merge_capped(|| state.merge(), text.len()) {
+            log::warn!(
+                "merge count exceeds text length {}, stopped with partial result",
+                text.len()
+            )
+        }
+        out.extend(state.iter());
+        *scratch = EncodeScratch {
+            marks: state.marks,
+            merges: state.merges,
+        }
+    }
+
+    fn begin_merge_in<'v, 't>(
+        &'v self,
+        text: &'t str,
+        scratch: EncodeScratch,
+        allowed: Option<&'v HashSet<utok>>,
+    ) -> MergeState<'v, 't> {
+        let is_allowed = |t: &utok| allowed.is_none_or(|set| set.contains(t));
+        let EncodeScratch {
+            mut marks,
+            mut merges,
+        } = scratch;
+        marks.clear();
+        marks.resize(text.len(), Mark::unk(self.unk));
+        merges.clear();
 
         let mut buf = [0u8; 4];
         let mut last = None;
         for (i, c) in text.char_indices() {
             let c = c.encode_utf8(&mut buf).as_bytes();
-            last = if let Some(token) = self.find_piece(c) {
+            last = if let Some(token) = self.find_piece(c).filter(is_allowed) {
                 marks[i].token = token;
                 if let Some(pos) = last.take() {
                     marks[i].back_distance = (i - pos) as _;
@@ -42,6 +107,7 @@ impl Bpe {
                         text.as_bytes(),
                         pos..i + c.len(),
                         (marks[pos].token, token),
+                        allowed,
                     ) {
                         merges.push(merge)
                     }
@@ -49,7 +115,8 @@ impl Bpe {
                 Some(i)
             } else {
                 for (&b, mark) in zip(c, &mut marks[i..]) {
-                    mark.token = self.bytes[b as usize]
+                    let byte = self.bytes[b as usize];
+                    mark.token = if is_allowed(&byte) { byte } else { self.unk }
                 }
                 None
             }
@@ -60,19 +127,43 @@ impl Bpe {
             bpe: self,
             marks,
             merges,
+            allowed,
         }
     }
 
-    fn build_merge(&self, text: &[u8], range: Range<usize>, pair: (utok, utok)) -> Option<Merge> {
-        self.find_piece(&text[range.clone()]).map(|merged| Merge {
+    fn build_merge(
+        &self,
+        text: &[u8],
+        range: Range<usize>,
+        pair: (utok, utok),
+        allowed: Option<&HashSet<utok>>,
+    ) -> Option<Merge> {
+        let merged = self.find_piece(&text[range.clone()])?;
+        if allowed.is_some_and(|set| !set.contains(&merged)) {
+            return None;
+        }
+        Some(Merge {
             pos: range.start,
             pair,
             merge: merged,
-            rank: self.token(merged).rank,
+            rank: match self.merge_overrides.get(&pair) {
+                Some(&rank) => rank,
+                None => self.token(merged).rank,
+            },
         })
     }
 }
 
+/// 重复执行 `merge` 直到失败，至多执行 `cap` 次成功的合并，超过上限时返回 `false`
+pub(super) fn merge_capped(mut merge: impl FnMut() -> bool, cap: usize) -> bool {
+    for _ in 0..cap {
+        if !merge() {
+            return true;
+        }
+    }
+    !merge()
+}
+
 #[derive(Clone, Copy, Debug)]
 struct Mark {
     token: utok,
@@ -121,7 +212,13 @@ impl PartialOrd for Merge {
 
 impl MergeState<'_, '_> {
     /// 尝试执行一次合并，返回是否成功执行了一次合并。
+    #[inline]
     pub fn merge(&mut self) -> bool {
+        self.merge_event().is_some()
+    }
+
+    /// 尝试执行一次合并，返回成功执行的合并。
+    pub fn merge_event(&mut self) -> Option<MergeEvent> {
         // 一次合并将涉及至多 4 个 token：
         //
         // t0 t1 t2 t3
@@ -142,7 +239,7 @@ impl MergeState<'_, '_> {
             pos: p1,
             pair: (t1, t2),
             merge,
-            ..
+            rank,
         }) = self.merges.pop()
         {
             // 确认合并项有效性
@@ -163,6 +260,8 @@ impl MergeState<'_, '_> {
             // 创建 merge + t3 合并项
             match self.marks.get_mut(p3) {
                 None => {}
+                // <unk> 只占 1 个字节，且不参与合并
+                Some(Mark { token, .. }) if *token == self.bpe.unk => {}
                 Some(Mark {
                     token,
                     back_distance,
@@ -172,7 +271,10 @@ impl MergeState<'_, '_> {
                     let t3 = *token;
                     let l3 = self.bpe.token(t3).len();
                     let p4 = p3 + l3;
-                    if let Some(merge) = self.bpe.build_merge(self.text, p1..p4, (merge, t3)) {
+                    if let Some(merge) =
+                        self.bpe
+                            .build_merge(self.text, p1..p4, (merge, t3), self.allowed)
+                    {
                         self.merges.push(merge)
                     }
                 }
@@ -183,15 +285,24 @@ impl MergeState<'_, '_> {
                 l0 => {
                     let p0 = p1 - l0;
                     let t0 = self.marks[p0].token;
-                    if let Some(merge) = self.bpe.build_merge(self.text, p0..p3, (t0, merge)) {
+                    if let Some(merge) =
+                        self.bpe
+                            .build_merge(self.text, p0..p3, (t0, merge), self.allowed)
+                    {
                         self.merges.push(merge)
                     }
                 }
             }
             // 成功合并
-            return true;
+            return Some(MergeEvent {
+                pos: p1,
+                left: t1,
+                right: t2,
+                merged: merge,
+                rank,
+            });
         }
-        false
+        None
     }
 
     #[inline]
@@ -2,11 +2,20 @@
 
 mod algorithm;
 
+pub use algorithm::{EncodeScratch, MergeEvent};
+
 use crate::{
-    Method, utok,
-    vocab::{CollectedVocab, CompressedVocab, TokenType},
+    FallbackMode, Method, json, utok,
+    vocab::{CollectedVocab, CompressedVocab, Fingerprint, TokenType, VocabError, display_piece},
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    iter::zip,
+    ops::{Deref, Range},
+    pin::Pin,
+    ptr::NonNull,
 };
-use std::{collections::HashSet, iter::zip, ops::Deref, pin::Pin, ptr::NonNull};
 
 pub struct Bpe {
     /// 保存所有词的字符串内容，以 u8 为单位所以不需要对齐，占用空间少
@@ -20,10 +29,43 @@ pub struct Bpe {
     bytes: Box<[utok; 256]>,
     /// 特殊词汇表
     special: Box<[utok]>,
+    /// `special` 中前 `n_special` 个是词表声明的特殊词，其余是内部不可达词
+    n_special: usize,
+    /// 无法匹配的字符的处理方式
+    fallback: FallbackMode,
+    /// 指定排名的合并，优先于合并结果的排名
+    merge_overrides: HashMap<(utok, utok), u32>,
+    /// 评分与合并先后的关系，追加新词时沿用
+    score_order: ScoreOrder,
     /// token: <unk>
     unk: utok,
 }
 
+/// 词的评分与合并先后的关系
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ScoreOrder {
+    /// 评分高的先合并，如 sentencepiece 的对数概率
+    #[default]
+    HigherFirst,
+    /// 评分低的先合并，如保存为负对数概率或合并序号的评分
+    LowerFirst,
+}
+
+/// 严格编码时，文本中存在无法编码的部分。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnkError {
+    /// 第一段无法编码的内容在文本中的字节范围
+    pub range: Range<usize>,
+}
+
+impl std::fmt::Display for UnkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bytes {:?} cannot be encoded without <unk>", self.range)
+    }
+}
+
+impl std::error::Error for UnkError {}
+
 struct TokenMeta {
     /// 指向字符串内容的指针
     ptr: NonNull<u8>,
@@ -80,22 +122,334 @@ impl Bpe {
                 0,
             ),
             scores,
+            ScoreOrder::HigherFirst,
         )
     }
 
+    /// 解析 json 格式导出的 SentencePiece 词表并构造一个 bpe 分词器。
+    ///
+    /// 词表是 `{"piece", "score", "type"}` 对象的数组，或者放在对象的 `pieces` 字段中。
+    /// `type` 缺省为 `NORMAL`，`UNUSED` 视为控制词，不会由普通文本产生。
+    /// 第一个 `UNKNOWN` 词作为 <unk>，没有时使用 0 号词。
+    pub fn from_sentencepiece_json(text: &str) -> Result<Self, VocabError> {
+        let value = json::parse(text).map_err(|pos| VocabError::InvalidJson { pos })?;
+        let pieces = value
+            .get("pieces")
+            .unwrap_or(&value)
+            .as_array()
+            .ok_or(VocabError::InvalidJson { pos: 0 })?;
+
+        let mut vocabs = Vec::with_capacity(pieces.len());
+        let mut scores = Vec::with_capacity(pieces.len());
+        let mut token_type = Vec::with_capacity(pieces.len());
+        for (index, item) in pieces.iter().enumerate() {
+            let err = || VocabError::InvalidPiece { index };
+            let piece = item
+                .get("piece")
+                .and_then(json::Value::as_str)
+                .ok_or_else(err)?;
+            let score = match item.get("score") {
+                Some(score) => score.as_f64().ok_or_else(err)? as f32,
+                None => 0.,
+            };
+            let tt = match item.get("type").map(json::Value::as_str) {
+                None | Some(Some("NORMAL")) => TokenType::Normal,
+                Some(Some("UNKNOWN")) => TokenType::Unknown,
+                Some(Some("CONTROL" | "UNUSED")) => TokenType::Control,
+                Some(Some("USER_DEFINED")) => TokenType::UserDefined,
+                Some(Some("BYTE")) => TokenType::Byte,
+                Some(_) => return Err(err()),
+            };
+            vocabs.push(piece);
+            scores.push(score);
+            token_type.push(tt)
+        }
+        let unk = token_type
+            .iter()
+            .position(|&tt| tt == TokenType::Unknown)
+            .unwrap_or(0) as utok;
+        Ok(Self::new(vocabs, scores, token_type, unk))
+    }
+
+    /// 解析 HuggingFace 格式的 tokenizer.json 并构造一个 bpe 分词器。
+    ///
+    /// 合词规则只决定合并的优先级：本分词器总是合并词表中存在的相邻片段，
+    /// 合并结果越早出现在 `merges` 中优先级越高，不由任何规则产生的词优先级最低。
+    /// `added_tokens` 中的词作为控制词（`special` 为 `false` 时作为用户定义词，
+    /// 同时以相同序号出现在 `vocab` 中时作为普通词），`model.unk_token` 作为 <unk>，没有时使用 0 号词。
+    pub fn from_hf_tokenizer_json(text: &str) -> Result<Self, VocabError> {
+        use json::Value;
+
+        let value = json::parse(text).map_err(|pos| VocabError::InvalidJson { pos })?;
+        let model = value
+            .get("model")
+            .ok_or(VocabError::InvalidJson { pos: 0 })?;
+        let Some(Value::Object(vocab)) = model.get("vocab") else {
+            return Err(VocabError::InvalidJson { pos: 0 });
+        };
+        let added = match value.get("added_tokens") {
+            Some(added) => added.as_array().ok_or(VocabError::InvalidJson { pos: 0 })?,
+            None => &[],
+        };
+        // 词序号可能不连续，先收集再检查
+        let as_id = |id: &Value| {
+            id.as_f64()
+                .filter(|&id| id >= 0. && id.fract() == 0.)
+                .map(|id| id as usize)
+        };
+        // 序号不能超过词的总数，避免按序号分配过多的空间
+        let n_pieces = vocab.len() + added.len();
+        let as_id = |id: &Value| as_id(id).filter(|&id| id < n_pieces);
+        let mut pieces = vec![None::<(&str, TokenType)>; n_pieces];
+        for (index, (piece, id)) in vocab.iter().enumerate() {
+            let id = as_id(id).ok_or(VocabError::InvalidPiece { index })?;
+            pieces[id] = Some((piece.as_str(), TokenType::Normal))
+        }
+        for (index, item) in added.iter().enumerate() {
+            let err = || VocabError::InvalidPiece {
+                index: vocab.len() + index,
+            };
+            let id = item.get("id").and_then(as_id).ok_or_else(err)?;
+            let piece = item
+                .get("content")
+                .and_then(Value::as_str)
+                .ok_or_else(err)?;
+            let tt = match item.get("special") {
+                // 同时出现在 vocab 中的非特殊词是普通词，如导出的内部不可达词
+                Some(Value::Bool(false))
+                    if pieces.get(id) == Some(&Some((piece, TokenType::Normal))) =>
+                {
+                    continue;
+                }
+                Some(Value::Bool(false)) => TokenType::UserDefined,
+                _ => TokenType::Control,
+            };
+            pieces[id] = Some((piece, tt))
+        }
+        // 序号可能重复，去掉末尾未使用的位置
+        let len = pieces
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |i| i + 1);
+        pieces.truncate(len);
+        let unk_piece = model.get("unk_token").and_then(Value::as_str);
+        let mut vocabs = Vec::with_capacity(pieces.len());
+        let mut token_type = Vec::with_capacity(pieces.len());
+        let mut unk = 0;
+        for (id, piece) in pieces.into_iter().enumerate() {
+            let (piece, mut tt) = piece.ok_or(VocabError::InvalidPiece { index: id })?;
+            if Some(piece) == unk_piece {
+                unk = id as utok;
+                tt = TokenType::Unknown
+            }
+            vocabs.push(piece);
+            token_type.push(tt)
+        }
+
+        let merges = match model.get("merges") {
+            Some(merges) => merges
+                .as_array()
+                .ok_or(VocabError::InvalidJson { pos: 0 })?,
+            None => &[],
+        };
+        let ids = zip(&vocabs, 0..)
+            .map(|(&piece, id)| (piece, id))
+            .collect::<std::collections::HashMap<_, usize>>();
+        let mut scores = vec![-(merges.len() as f32); vocabs.len()];
+        for (index, merge) in merges.iter().enumerate().rev() {
+            let err = || VocabError::InvalidPiece { index };
+            // 旧版格式为 "left right"，新版为 ["left", "right"]
+            let merged = match merge {
+                Value::String(s) => s.split_once(' ').map(|(l, r)| format!("{l}{r}")),
+                Value::Array(pair) => match pair.as_slice() {
+                    [Value::String(l), Value::String(r)] => Some(format!("{l}{r}")),
+                    _ => None,
+                },
+                _ => None,
+            }
+            .ok_or_else(err)?;
+            if let Some(&id) = ids.get(&*merged) {
+                scores[id] = -(index as f32)
+            }
+        }
+        Ok(Self::new(vocabs, scores, token_type, unk))
+    }
+
     pub fn new<'a>(
         vocabs: impl IntoIterator<Item = &'a str>,
         scores: impl IntoIterator<Item = f32>,
         token_type: impl IntoIterator<Item = TokenType>,
         unk: utok,
     ) -> Self {
-        Self::from_collected_vocab(
-            CollectedVocab::collect(vocabs.into_iter().map(|s| s.as_bytes()), token_type, unk),
+        let (vocab, scores) = CollectedVocab::collect_with_scores(
+            vocabs.into_iter().map(|s| s.as_bytes()),
             scores,
-        )
+            token_type,
+            unk,
+        );
+        Self::from_collected_vocab(vocab, scores, ScoreOrder::HigherFirst)
+    }
+
+    /// 与 [`Bpe::new`] 相同，由 `order` 指定评分与合并先后的关系
+    pub fn new_with_score_order<'a>(
+        vocabs: impl IntoIterator<Item = &'a str>,
+        scores: impl IntoIterator<Item = f32>,
+        token_type: impl IntoIterator<Item = TokenType>,
+        unk: utok,
+        order: ScoreOrder,
+    ) -> Self {
+        let (vocab, scores) = CollectedVocab::collect_with_scores(
+            vocabs.into_iter().map(|s| s.as_bytes()),
+            scores,
+            token_type,
+            unk,
+        );
+        Self::from_collected_vocab(vocab, scores, order)
+    }
+
+    /// 与 [`Bpe::new`] 相同，但使用预先计算的内部不可达词，跳过构造时的扫描。
+    pub fn new_with_inaccessible<'a>(
+        vocabs: impl IntoIterator<Item = &'a str>,
+        scores: impl IntoIterator<Item = f32>,
+        token_type: impl IntoIterator<Item = TokenType>,
+        unk: utok,
+        inaccessible: &[utok],
+    ) -> Self {
+        let (vocab, scores) = CollectedVocab::collect_with_scores(
+            vocabs.into_iter().map(|s| s.as_bytes()),
+            scores,
+            token_type,
+            unk,
+        );
+        Self::from_collected_vocab_with_inaccessible(vocab, scores, inaccessible)
+    }
+
+    fn from_collected_vocab(
+        vocab: CollectedVocab,
+        scores: impl IntoIterator<Item = f32>,
+        order: ScoreOrder,
+    ) -> Self {
+        let mut ans = Self::build(vocab, scores, order);
+        #[cfg(not(feature = "parallel"))]
+        let inaccessible = ans.inaccessible();
+        #[cfg(feature = "parallel")]
+        let inaccessible = ans.inaccessible_parallel();
+        ans.special = ans.special.into_iter().chain(inaccessible).collect();
+        ans
+    }
+
+    /// 使用预先计算的内部不可达词构造分词器，不再扫描词表。
+    pub(crate) fn from_collected_vocab_with_inaccessible(
+        vocab: CollectedVocab,
+        scores: impl IntoIterator<Item = f32>,
+        inaccessible: &[utok],
+    ) -> Self {
+        let mut ans = Self::build(vocab, scores, ScoreOrder::HigherFirst);
+        ans.special = ans.special.iter().chain(inaccessible).copied().collect();
+        ans
+    }
+
+    /// 向词表追加新词，已有词的序号和合并优先级保持不变。
+    ///
+    /// 新词的序号从原词表大小开始连续分配，合并优先级低于所有已有词，新词之间按评分排序。
+    /// 追加后重新计算内部不可达词。
+    pub fn extend<'a>(&mut self, new_tokens: impl IntoIterator<Item = (&'a str, f32, TokenType)>) {
+        let (new_tokens, scores): (Vec<_>, Vec<_>) = new_tokens
+            .into_iter()
+            .map(|(piece, score, tt)| ((piece.as_bytes(), tt), score))
+            .unzip();
+        if new_tokens.is_empty() {
+            return;
+        }
+        let base = self.tokens.len() as utok;
+        // 使用不可能出现的序号标记未出现的字节
+        let new = CollectedVocab::collect(
+            new_tokens.iter().map(|&(piece, _)| piece),
+            new_tokens.iter().map(|&(_, tt)| tt),
+            utok::MAX,
+        );
+
+        let mut bytes = self.bytes.clone();
+        for (slot, &t) in zip(&mut *bytes, &*new.bytes) {
+            if t != utok::MAX {
+                *slot = base + t
+            }
+        }
+        // 内部不可达词在构造后重新检查，这里只保留声明的特殊词
+        let special = self.special[..self.n_special]
+            .iter()
+            .copied()
+            .chain(new.special.iter().map(|&t| base + t))
+            .collect();
+        let vocabs = self
+            .tokens
+            .iter()
+            .map(|t| &**t)
+            .chain(new.vocabs)
+            .collect::<Vec<_>>();
+        let total_len = vocabs.iter().map(|s| s.len()).sum();
+        let max_rank = self
+            .tokens
+            .iter()
+            .map(|t| t.rank)
+            .max()
+            .map_or(0, |r| r + 1);
+        let ranks = self
+            .tokens
+            .iter()
+            .map(|t| t.rank)
+            .chain(
+                rank(&scores, self.score_order)
+                    .into_iter()
+                    .map(|r| max_rank + r),
+            )
+            .collect();
+
+        let mut ans = Self::build_ranked(
+            CollectedVocab {
+                vocabs,
+                total_len,
+                bytes,
+                special,
+                unk: self.unk,
+            },
+            ranks,
+        );
+        // 原有词的序号不变，指定的合并排名仍然有效
+        ans.merge_overrides = std::mem::take(&mut self.merge_overrides);
+        ans.score_order = self.score_order;
+        // 新词可能使原来内部不可达的词变得可达，因此重新检查整个词表
+        #[cfg(not(feature = "parallel"))]
+        let inaccessible = ans.inaccessible();
+        #[cfg(feature = "parallel")]
+        let inaccessible = ans.inaccessible_parallel();
+        ans.special = ans.special.iter().chain(&inaccessible).copied().collect();
+        ans.fallback = self.fallback;
+        *self = ans
+    }
+
+    /// 构造分词器，不包括内部不可达词
+    fn build(
+        vocab: CollectedVocab,
+        scores: impl IntoIterator<Item = f32>,
+        order: ScoreOrder,
+    ) -> Self {
+        // 收集合词评分
+        let scores = scores.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            vocab.vocabs.len(),
+            scores.len(),
+            "scores size mismatch with vocab size"
+        );
+        // 重新赋权并转换为整型的分词评分
+        let ranks = rank(&scores, order).into_iter().collect();
+        let mut ans = Self::build_ranked(vocab, ranks);
+        ans.score_order = order;
+        ans
     }
 
-    fn from_collected_vocab(vocab: CollectedVocab, scores: impl IntoIterator<Item = f32>) -> Self {
+    /// 使用整型的合并排名构造分词器，不包括内部不可达词
+    fn build_ranked(vocab: CollectedVocab, ranks: Vec<u32>) -> Self {
         let CollectedVocab {
             vocabs,
             total_len,
@@ -103,16 +457,10 @@ impl Bpe {
             special,
             unk,
         } = vocab;
+        let n_special = special.len();
         let CompressedVocab { vocabs, slices } = CompressedVocab::new(&vocabs, total_len);
-        // 收集合词评分
-        let scores = scores.into_iter().collect::<Vec<_>>();
-        assert_eq!(
-            slices.len(),
-            scores.len(),
-            "scores size mismatch with vocab size"
-        );
-        // tokens 中直接引用字符串位置，绑定重新赋权并转换为整型的分词评分
-        let tokens = zip(slices, rank(&scores))
+        // tokens 中直接引用字符串位置，绑定合并排名
+        let tokens = zip(slices, ranks)
             .map(|((off, len), rank)| TokenMeta {
                 ptr: unsafe { NonNull::new_unchecked(vocabs[off..].as_ptr().cast_mut()) },
                 len: len as _,
@@ -125,7 +473,15 @@ impl Bpe {
         let mut sorted_pieces = (0..tokens.len() as utok)
             .filter(|i| !bytes_set.contains(i))
             .collect::<Box<_>>();
-        sorted_pieces.sort_unstable_by_key(|&i| &*tokens[i as usize]);
+        // 相同的 piece 按序号排序，使结果与排序算法无关
+        let key = |&i: &utok| (&*tokens[i as usize], i);
+        #[cfg(not(feature = "parallel"))]
+        sorted_pieces.sort_unstable_by_key(key);
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::slice::ParallelSliceMut;
+            sorted_pieces.par_sort_unstable_by_key(key)
+        }
 
         // println!(
         //     "Building BPE vocab, detected {} tokens, compressed to {} bytes from {total_len} bytes",
@@ -133,20 +489,22 @@ impl Bpe {
         //     vocabs.len(),
         // );
 
-        let mut ans = Self {
+        Self {
             _vocabs: vocabs,
             tokens,
             sorted_pieces,
             bytes,
             special,
+            n_special,
             unk,
-        };
-        let inaccessible = ans.inaccessible();
-        ans.special = ans.special.into_iter().chain(inaccessible).collect();
-        ans
+            fallback: FallbackMode::ByteFallback,
+            merge_overrides: HashMap::new(),
+            score_order: ScoreOrder::HigherFirst,
+        }
     }
 
     /// BPE 词表中，并非所有词都是合词规则可达的。此算法可识别“内部不可达”的 token。
+    #[cfg_attr(feature = "parallel", allow(unused))]
     fn inaccessible(&self) -> Vec<utok> {
         self.sorted_pieces
             .iter()
@@ -161,6 +519,280 @@ impl Bpe {
             .collect()
     }
 
+    /// 并行版本的 [`Bpe::inaccessible`]，每个 token 的编码互相独立。
+    #[cfg(feature = "parallel")]
+    fn inaccessible_parallel(&self) -> Vec<utok> {
+        use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+        self.sorted_pieces
+            .par_iter()
+            .filter_map(|&t| {
+                let s = unsafe { std::str::from_utf8_unchecked(self.token(t)) };
+                if self.encode(s).into_iter().nth(1).is_some() {
+                    Some(t)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// 检查词表是否包含全部 256 个单字节词，返回缺失的字节。
+    ///
+    /// 缺少单字节词时，编码任意字节可能意外地产生 <unk>。
+    pub fn verify_byte_coverage(&self) -> Result<(), Vec<u8>> {
+        let missing = (0..=u8::MAX)
+            .filter(|&b| self.bytes[b as usize] == self.unk)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// 导出为 HuggingFace 格式的 tokenizer.json，与 [`Bpe::from_hf_tokenizer_json`] 互逆。
+    ///
+    /// 合词规则与 [`Bpe::export_merges`] 相同，单字节词写作 `<0xXY>`，
+    /// [`Method::internal_special`] 中的词导出为 `added_tokens`，其中内部不可达词不是特殊词。
+    /// 词表中有不是 utf-8 的多字节词时返回错误。
+    pub fn to_hf_tokenizer_json(&self) -> Result<String, VocabError> {
+        use json::Value;
+
+        let mut byte_of = std::collections::HashMap::new();
+        for (b, &t) in self.bytes.iter().enumerate() {
+            if t != self.unk {
+                byte_of.insert(t, b as u8);
+            }
+        }
+        let pieces = (0..self.tokens.len() as utok)
+            .map(|t| match byte_of.get(&t) {
+                Some(b) => Ok(format!("<0x{b:02X}>")),
+                None => std::str::from_utf8(self.token(t))
+                    .map(str::to_string)
+                    .map_err(|_| VocabError::InvalidPiece { index: t as _ }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let piece = |t: utok| Value::String(pieces[t as usize].clone());
+        let vocab = zip(&pieces, 0..)
+            .map(|(p, t)| (p.clone(), Value::Number(t as _)))
+            .collect();
+
+        let merges = self
+            .merge_pairs()
+            .into_iter()
+            .map(|(l, r)| Value::Array(vec![piece(l), piece(r)]))
+            .collect();
+
+        let declared = &self.special[..self.n_special];
+        let added_tokens = self
+            .special
+            .iter()
+            .enumerate()
+            .filter(|&(i, t)| i < self.n_special || !declared.contains(t))
+            .map(|(i, &t)| {
+                Value::Object(vec![
+                    ("id".into(), Value::Number(t as _)),
+                    ("content".into(), piece(t)),
+                    ("special".into(), Value::Bool(i < self.n_special)),
+                ])
+            })
+            .collect();
+        Ok(Value::Object(vec![
+            ("version".into(), Value::String("1.0".into())),
+            ("added_tokens".into(), Value::Array(added_tokens)),
+            (
+                "model".into(),
+                Value::Object(vec![
+                    ("type".into(), Value::String("BPE".into())),
+                    ("unk_token".into(), piece(self.unk)),
+                    ("byte_fallback".into(), Value::Bool(!byte_of.is_empty())),
+                    ("vocab".into(), Value::Object(vocab)),
+                    ("merges".into(), Value::Array(merges)),
+                ]),
+            ),
+        ])
+        .to_string())
+    }
+
+    /// 按合并排名导出合词规则，每个能由合并得到的多字节词对应一条规则。
+    ///
+    /// 词拆分为编码这个词时最后一次合并的两个词，因此导出的规则与编码的行为一致；
+    /// 内部不可达的词、不是 utf-8 的词和特殊词没有对应的规则。
+    pub fn export_merges(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.merge_pairs()
+            .into_iter()
+            .map(|(l, r)| (self.token(l).to_vec(), self.token(r).to_vec()))
+            .collect()
+    }
+
+    /// 按合并排名给出 [`Bpe::export_merges`] 中每条规则的左右两个词
+    fn merge_pairs(&self) -> Vec<(utok, utok)> {
+        let special = self.special.iter().collect::<HashSet<_>>();
+        let mut merges = self
+            .sorted_pieces
+            .iter()
+            .filter(|t| !special.contains(t))
+            .filter_map(|&t| {
+                let piece = std::str::from_utf8(self.token(t)).ok()?;
+                let last = self.encode_trace(piece).pop()?;
+                (last.merged == t).then_some((self.token(t).rank, t, last.left, last.right))
+            })
+            .collect::<Vec<_>>();
+        merges.sort_unstable();
+        merges.into_iter().map(|(_, _, l, r)| (l, r)).collect()
+    }
+
+    /// 编码文本并将结果追加到 `out`，与 [`Method::encode`] 的结果相同。
+    ///
+    /// 编码的中间存储保存在 `scratch` 中，循环中复用 `out` 和 `scratch` 可以避免重复分配内存。
+    pub fn encode_into(&self, text: &str, out: &mut Vec<utok>, scratch: &mut EncodeScratch) {
+        if self.fallback == FallbackMode::ByteFallback {
+            return self.merge_into(text, out, scratch);
+        }
+        // 合并不会跨越无法匹配的字符，因此可以在这些字符处切分
+        let mut start = 0;
+        let mut buf = [0u8; 4];
+        for (i, c) in text.char_indices() {
+            if self
+                .find_piece(c.encode_utf8(&mut buf).as_bytes())
+                .is_none()
+            {
+                self.merge_into(&text[start..i], out, scratch);
+                if self.fallback == FallbackMode::Unk {
+                    out.push(self.unk)
+                }
+                start = i + c.len_utf8()
+            }
+        }
+        self.merge_into(&text[start..], out, scratch)
+    }
+
+    /// 设置无法匹配的字符的处理方式，默认为 [`FallbackMode::ByteFallback`]
+    pub fn set_fallback(&mut self, mode: FallbackMode) {
+        self.fallback = mode
+    }
+
+    /// 指定一些合并的排名，覆盖由合并结果的评分得到的排名，用于复现参考实现的特殊行为。
+    ///
+    /// 排名越小越先合并，与已有的指定合并冲突时以新的为准。
+    pub fn with_merge_overrides(&mut self, overrides: HashMap<(utok, utok), u32>) {
+        self.merge_overrides.extend(overrides)
+    }
+
+    /// 列出合词规则无法产生的词，按序号升序排列，用于审查词表。
+    ///
+    /// 这些词的文本编码后总是得到多个词，可以配合 [`Bpe::token_piece_display`] 输出可读的结果。
+    pub fn inaccessible_tokens(&self) -> Vec<utok> {
+        #[cfg(not(feature = "parallel"))]
+        let mut ans = self.inaccessible();
+        #[cfg(feature = "parallel")]
+        let mut ans = self.inaccessible_parallel();
+        ans.sort_unstable();
+        ans
+    }
+
+    /// 以便于阅读的形式渲染 token 内容，用于调试词表。
+    #[inline]
+    pub fn token_piece_display(&self, token: utok) -> String {
+        display_piece(self.token(token))
+    }
+
+    /// 保存所有词内容的字节串，相同或互为前后缀的词共享存储
+    #[inline]
+    pub fn vocab_blob(&self) -> &[u8] {
+        &self._vocabs
+    }
+
+    /// 词的内容在 [`Bpe::vocab_blob`] 中的字节偏移和长度
+    pub fn token_offset(&self, token: utok) -> (usize, usize) {
+        let meta = self.token(token);
+        // SAFETY: 词的指针总是指向 _vocabs 内部
+        let off = unsafe { meta.ptr.as_ptr().offset_from(self._vocabs.as_ptr()) };
+        (off as _, meta.len as _)
+    }
+
+    /// 在已有的编码结果后追加文本，只重新编码受影响的尾部，用于对话中复用前缀的编码。
+    ///
+    /// 从 `prior_tokens` 中最后一个以空白开头的 token 处开始重新编码（若此处切分的尾部不是完整的 utf-8，继续向前寻找），
+    /// 之前的 token 原样保留。
+    ///
+    /// 注意：bpe 本身不做预分词，若词表中存在跨越空白的词，边界处的结果可能与完整编码不同。
+    pub fn encode_continuation(&self, prior_tokens: &[utok], new_text: &str) -> Vec<utok> {
+        let mut start = prior_tokens.len();
+        let text = loop {
+            start = prior_tokens[..start]
+                .iter()
+                .rposition(|&t| matches!(self.decode(t), [b' ', ..] | [0xe2, 0x96, 0x81, ..]))
+                .unwrap_or(0);
+            let mut text = prior_tokens[start..]
+                .iter()
+                .flat_map(|&t| self.decode(t))
+                .copied()
+                .collect::<Vec<_>>();
+            text.extend_from_slice(new_text.as_bytes());
+            match String::from_utf8(text) {
+                Ok(text) => break text,
+                Err(e) if start == 0 => break String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                Err(_) => {}
+            }
+        };
+
+        let mut ans = prior_tokens[..start].to_vec();
+        ans.extend(self.encode(&text));
+        ans
+    }
+
+    /// 编码文本，文本中存在词表无法覆盖的字节时返回错误而不是产生 <unk>。
+    ///
+    /// 错误的范围由文本计算，与 [`FallbackMode`] 无关。
+    pub fn encode_strict(&self, text: &str) -> Result<Vec<utok>, UnkError> {
+        // 无法匹配的字符中，没有对应单字节词的字节无法覆盖，取第一段连续的这类字节
+        let mut buf = [0u8; 4];
+        let mut unk = None::<Range<usize>>;
+        'chars: for (i, c) in text.char_indices() {
+            let piece = c.encode_utf8(&mut buf).as_bytes();
+            let matched = self.find_piece(piece).is_some();
+            for (pos, &b) in zip(i.., piece) {
+                if !matched && self.bytes[b as usize] == self.unk {
+                    unk.get_or_insert(pos..pos).end = pos + 1
+                } else if unk.is_some() {
+                    break 'chars;
+                }
+            }
+        }
+        match unk {
+            Some(range) => Err(UnkError { range }),
+            None => Ok(self.encode(text).into_iter().collect()),
+        }
+    }
+
+    /// 只使用 `allowed` 中的词编码文本，用于将文本编码到词表的子集。
+    ///
+    /// 只执行产生允许的词的合并，不允许的字符逐字节编码，不允许的单字节词编码为 unk。
+    pub fn encode_restricted(&self, text: &str, allowed: &HashSet<utok>) -> Vec<utok> {
+        let mut state = self.begin_merge_restricted(text, allowed);
+        while state.merge() {}
+        state.into_iter().collect()
+    }
+
+    /// 逐字节编码为单字节词，不做任何合并，词表中没有对应单字节词的字节编码为 unk。
+    pub fn bytes_to_tokens(&self, bytes: &[u8]) -> Vec<utok> {
+        bytes.iter().map(|&b| self.bytes[b as usize]).collect()
+    }
+
+    /// 编码文本并按执行顺序记录每一次合并，用于诊断分词结果。
+    pub fn encode_trace(&self, text: &str) -> Vec<MergeEvent> {
+        let mut tokenizer = self.begin_merge(text);
+        std::iter::from_fn(|| tokenizer.merge_event()).collect()
+    }
+
+    /// 判断 `piece` 是否恰好是词表中的一个词，包括单字节词，不包括 unk。
+    #[inline]
+    pub fn contains_piece(&self, piece: &[u8]) -> bool {
+        self.find_piece(piece).is_some_and(|t| t != self.unk)
+    }
+
     /// piece -> token
     #[inline]
     fn find_piece(&self, piece: &[u8]) -> Option<utok> {
@@ -169,8 +801,9 @@ impl Bpe {
             .binary_search_by_key(&piece, |&i| self.token(i))
         {
             Ok(i) => Some(self.sorted_pieces[i]),
+            // 词表中没有对应单字节词的字节视为无法匹配
             Err(_) => match *piece {
-                [b] => Some(self.bytes[b as usize]),
+                [b] => Some(self.bytes[b as usize]).filter(|&t| t != self.unk),
                 [..] => None,
             },
         }
@@ -201,59 +834,70 @@ impl Method for Bpe {
     }
     #[inline]
     fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
-        let mut tokenizer = self.begin_merge(text);
-        while tokenizer.merge() {}
-        tokenizer.into_iter()
+        let mut ans = Vec::new();
+        self.encode_into(text, &mut ans, &mut EncodeScratch::default());
+        ans
     }
     #[inline]
     fn decode(&self, token: utok) -> &[u8] {
         self.token(token)
     }
-}
-
-/// 对一组评分排序、去重并重新赋权，转换为保持相同顺序的整型序列
-fn rank(scores: &[f32]) -> impl IntoIterator<Item = u32> + '_ {
-    use std::{
-        cmp::Ordering,
-        collections::{BTreeMap, BTreeSet},
-    };
-
-    #[derive(Debug)]
-    struct FloatOrd(f32);
-    impl Eq for FloatOrd {}
-    impl PartialEq for FloatOrd {
-        #[inline]
-        fn eq(&self, other: &Self) -> bool {
-            self.0.total_cmp(&other.0).is_eq()
-        }
+    #[inline]
+    fn decoded_len(&self, token: utok) -> usize {
+        self.token(token).len as _
+    }
+    fn max_token_len(&self) -> usize {
+        self.tokens
+            .iter()
+            .map(|t| t.len as usize)
+            .max()
+            .unwrap_or(0)
+    }
+    #[inline]
+    fn token_of(&self, piece: &str) -> Option<utok> {
+        self.find_piece(piece.as_bytes())
     }
-    impl PartialOrd for FloatOrd {
-        #[inline]
-        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-            Some(self.cmp(other))
+    /// 非法的 utf-8 字节编码为单字节词
+    fn encode_bytes(&self, bytes: &[u8]) -> Vec<utok> {
+        let mut ans = Vec::new();
+        for chunk in bytes.utf8_chunks() {
+            ans.extend(self.encode(chunk.valid()));
+            ans.extend(self.bytes_to_tokens(chunk.invalid()))
         }
+        ans
     }
-    impl Ord for FloatOrd {
-        #[inline]
-        fn cmp(&self, other: &Self) -> Ordering {
-            self.0.total_cmp(&other.0)
+    /// 指纹包含合并排名
+    fn fingerprint(&self) -> u64 {
+        let mut ans = Fingerprint::vocab(self);
+        for t in &self.tokens {
+            ans.write_u32(t.rank)
         }
+        ans.finish()
+    }
+    #[inline]
+    fn iter_vocab(&self) -> impl Iterator<Item = (utok, Cow<'_, [u8]>)> + '_ {
+        (0..).zip(self.tokens.iter().map(|t| Cow::Borrowed(&**t)))
     }
+}
 
-    let map = scores
-        // 排序 + 去重
-        .iter()
-        .copied()
-        .map(FloatOrd)
-        .collect::<BTreeSet<_>>()
-        // 重新赋权
-        .into_iter()
-        .rev()
-        .enumerate()
-        .map(|(i, f)| (f, i as u32))
-        .collect::<BTreeMap<_, _>>();
-
-    scores.iter().map(move |&f| map[&FloatOrd(f)])
+/// 对一组评分排序并重新赋权，转换为保持相同顺序的整型序列。
+///
+/// 评分高的排名靠前，评分相同时序号小的排名靠前，因此每个词的排名都不相同。
+fn rank(scores: &[f32], order: ScoreOrder) -> impl IntoIterator<Item = u32> + '_ {
+    let mut indices = (0..scores.len()).collect::<Vec<_>>();
+    indices.sort_unstable_by(|&a, &b| {
+        match order {
+            ScoreOrder::HigherFirst => scores[b].total_cmp(&scores[a]),
+            ScoreOrder::LowerFirst => scores[a].total_cmp(&scores[b]),
+        }
+        .then(a.cmp(&b))
+    });
+
+    let mut ranks = vec![0; scores.len()];
+    for (r, i) in indices.into_iter().enumerate() {
+        ranks[i] = r as u32
+    }
+    ranks
 }
 
 #[cfg(test)]
@@ -381,4 +1025,711 @@ mod bpe_tests {
         let encoded: Vec<_> = bpe.encode("aAB").into_iter().collect();
         assert_eq!(encoded, [0, 2, 3], "Expected 3 tokens for input 'aAB'")
     }
+
+    #[test]
+    fn test_bpe_token_piece_display() {
+        let vocabs = ["<unk>", "\u{2581}", "<0x0A>", "hello"];
+        let token_type = [
+            TokenType::Unknown,
+            TokenType::Normal,
+            TokenType::Byte,
+            TokenType::Normal,
+        ];
+        let bpe = Bpe::new(vocabs, [0.; 4], token_type, 0);
+
+        assert_eq!(bpe.token_piece_display(1), "_");
+        assert_eq!(bpe.token_piece_display(2), "<0x0A>");
+        assert_eq!(bpe.token_piece_display(3), "hello")
+    }
+
+    #[test]
+    fn test_bpe_scores_aligned() {
+        // 包含重复的词，且词类型比词表少一项
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "ab", "ab", "ba"],
+            [0., 1., 1., 3., 2., 4.],
+            [TokenType::Normal; 5],
+            0,
+        );
+        assert_eq!(bpe.vocab_size(), 5);
+        let ranks = bpe.tokens.iter().map(|t| t.rank).collect::<Vec<_>>();
+        // 评分相同的 a 和 b 按序号区分排名
+        assert_eq!(ranks, [4, 2, 3, 0, 1])
+    }
+
+    #[test]
+    fn test_bpe_encode_continuation() {
+        let bpe = Bpe::new(
+            ["<unk>", " ", "a", "b", " a", " b", "ab", " ab"],
+            [0., 1., 1., 1., 2., 2., 3., 4.],
+            [TokenType::Normal; 8],
+            0,
+        );
+        let prior = bpe.encode(" ab a").into_iter().collect::<Vec<_>>();
+        for new_text in [" b", "b", " ab b", ""] {
+            let full = bpe
+                .encode(&format!(" ab a{new_text}"))
+                .into_iter()
+                .collect::<Vec<_>>();
+            assert_eq!(bpe.encode_continuation(&prior, new_text), full);
+        }
+        assert_eq!(
+            bpe.encode_continuation(&[], " ab"),
+            bpe.encode(" ab").into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_bpe_token_of() {
+        let bpe = test_bpe();
+        assert_eq!(bpe.token_of("bcd"), Some(9));
+        assert_eq!(bpe.token_of("a"), Some(1));
+        assert_eq!(bpe.token_of("bc"), None);
+        assert_eq!(bpe.token_of("z"), None);
+        assert_eq!(bpe.token_of("<unk>"), None);
+    }
+
+    #[test]
+    fn test_bpe_iter_vocab() {
+        let bpe = test_bpe();
+        let vocab = bpe.iter_vocab().collect::<Vec<_>>();
+        assert_eq!(vocab.len(), bpe.vocab_size());
+        assert!(vocab.iter().enumerate().all(|(i, (t, _))| i as utok == *t));
+        assert_eq!(&*vocab[9].1, b"bcd");
+    }
+
+    #[test]
+    fn test_bpe_from_sentencepiece_json() {
+        let json = r#"{"pieces": [
+            {"piece": "<unk>", "score": 0.0, "type": "UNKNOWN"},
+            {"piece": "<s>", "score": 0.0, "type": "CONTROL"},
+            {"piece": "<0x0A>", "score": 0.0, "type": "BYTE"},
+            {"piece": "a", "score": -1.0, "type": "NORMAL"},
+            {"piece": "b", "score": -1.0},
+            {"piece": "ab", "score": -0.5, "type": "NORMAL"}
+        ]}"#;
+        let bpe = Bpe::from_sentencepiece_json(json).unwrap();
+        assert_eq!(bpe.vocab_size(), 6);
+        assert_eq!(bpe.unk_token(), 0);
+        assert_eq!(bpe.decode(2), b"\n");
+        assert_eq!(bpe.encode("ab\n").into_iter().collect::<Vec<_>>(), [5, 2]);
+        let special = bpe.internal_special().into_iter().collect::<Vec<_>>();
+        assert!(special.contains(&("<s>", 1)));
+
+        assert_eq!(
+            Bpe::from_sentencepiece_json(r#"[{"score": 0.0}]"#).err(),
+            Some(VocabError::InvalidPiece { index: 0 })
+        );
+        assert_eq!(
+            Bpe::from_sentencepiece_json("[{").err(),
+            Some(VocabError::InvalidJson { pos: 2 })
+        );
+    }
+
+    #[test]
+    fn test_bpe_encode_trace() {
+        let bpe = test_bpe();
+        let trace = bpe.encode_trace("abcd");
+        assert_eq!(
+            trace,
+            [MergeEvent {
+                pos: 0,
+                left: 1,
+                right: 2,
+                merged: 5,
+                rank: 4,
+            }]
+        );
+        // "bd" 的排名高于 "ab"，先合并后 "ab" 不再有效
+        let trace = bpe.encode_trace("abd");
+        assert_eq!(
+            trace,
+            [MergeEvent {
+                pos: 1,
+                left: 2,
+                right: 4,
+                merged: 8,
+                rank: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_bpe_encode_truncated() {
+        use crate::TruncSide;
+
+        let bpe = test_bpe();
+        let text = "abdacadbdab";
+        let full = bpe.encode(text).into_iter().collect::<Vec<_>>();
+        assert_eq!(full.len(), 6);
+
+        let right = bpe.encode_truncated(text, 4, TruncSide::Right);
+        assert_eq!(right, full[..4]);
+        let left = bpe.encode_truncated(text, 4, TruncSide::Left);
+        assert_eq!(left, full[2..]);
+        assert_eq!(bpe.encode_truncated(text, 100, TruncSide::Left), full);
+        assert!(bpe.encode_truncated(text, 0, TruncSide::Right).is_empty());
+    }
+
+    #[test]
+    fn test_bpe_encode_strict() {
+        let bpe = test_bpe();
+        assert_eq!(bpe.encode_strict("abd").unwrap(), [1, 8]);
+        assert_eq!(bpe.encode_strict("ab\ncd"), Err(UnkError { range: 2..3 }));
+        assert_eq!(bpe.encode_strict("a中b"), Err(UnkError { range: 1..4 }));
+        assert_eq!(bpe.encode_strict("a中中b"), Err(UnkError { range: 1..7 }));
+    }
+
+    #[test]
+    fn test_bpe_encode_strict_fallback_mode() {
+        let mut bpe = test_bpe();
+        for mode in [FallbackMode::Unk, FallbackMode::Skip] {
+            bpe.set_fallback(mode);
+            assert_eq!(bpe.encode_strict("abd").unwrap(), [1, 8]);
+            assert_eq!(bpe.encode_strict("a中b"), Err(UnkError { range: 1..4 }));
+            assert_eq!(bpe.encode_strict("a中中b"), Err(UnkError { range: 1..7 }));
+            // 只报告第一段，后面的范围不受前面多字节字符的影响
+            assert_eq!(bpe.encode_strict("ab中a中"), Err(UnkError { range: 2..5 }));
+            assert_eq!(bpe.encode_strict("abd中"), Err(UnkError { range: 3..6 }));
+        }
+    }
+
+    #[test]
+    fn test_bpe_encode_with_special() {
+        let bpe = test_bpe();
+        assert_eq!(bpe.encode_with_special("abd", true, true), [1, 8]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_bpe_inaccessible_parallel() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "c", "ab", "bc", "abc", "ab", "ca"],
+            [0., 1., 1., 1., 2., 3., 4., 2., 0.5],
+            [TokenType::Normal; 9],
+            0,
+        );
+        let mut sorted = bpe.sorted_pieces.to_vec();
+        sorted.sort_by_key(|&i| (&**bpe.token(i), i));
+        assert_eq!(&*bpe.sorted_pieces, sorted);
+        assert_eq!(bpe.inaccessible_parallel(), bpe.inaccessible());
+    }
+
+    #[test]
+    fn test_bpe_new_with_inaccessible() {
+        let vocabs = ["<unk>", "a", "b", "c", "d", "ab", "ac", "ad", "bd", "bcd"];
+        let scores = [0., 1., 1., 1., 1., 1.1, 1.2, 1.3, 1.4, 10.];
+        let computed = test_bpe();
+        let inaccessible = computed.inaccessible();
+        assert_eq!(inaccessible, [9]);
+
+        let supplied =
+            Bpe::new_with_inaccessible(vocabs, scores, [TokenType::Normal; 10], 0, &inaccessible);
+        assert!(
+            supplied
+                .internal_special()
+                .into_iter()
+                .eq(computed.internal_special())
+        );
+        for text in ["abcd", "bcd", "abd", "dcba"] {
+            assert_eq!(
+                supplied.encode(text).into_iter().collect::<Vec<_>>(),
+                computed.encode(text).into_iter().collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_bpe_extend() {
+        let mut bpe = test_bpe();
+        let texts = ["abcd", "abd", "dcba", "bcd"];
+        let encode = |bpe: &Bpe| {
+            texts
+                .iter()
+                .map(|t| bpe.encode(t).into_iter().collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let before = encode(&bpe);
+
+        bpe.extend([
+            ("e", 0.5, TokenType::Normal),
+            ("de", 0.6, TokenType::Normal),
+            ("<0x0A>", 0., TokenType::Byte),
+            ("<pad>", 0., TokenType::Control),
+        ]);
+        assert_eq!(bpe.vocab_size(), 14);
+        assert_eq!(encode(&bpe), before);
+        assert_eq!(bpe.decode(9), b"bcd");
+        assert_eq!(
+            bpe.encode("cde\n").into_iter().collect::<Vec<_>>(),
+            [3, 11, 12]
+        );
+        assert!(
+            bpe.internal_special()
+                .into_iter()
+                .any(|(s, t)| s == "<pad>" && t == 13)
+        );
+    }
+
+    #[test]
+    fn test_bpe_extend_reachable() {
+        let mut bpe = test_bpe();
+        // "bcd" 原本内部不可达，追加 "cd" 后可以由 "b" 和 "cd" 合并得到
+        assert_eq!(bpe.inaccessible_tokens(), [9]);
+        assert!(bpe.internal_special().into_iter().any(|(_, t)| t == 9));
+        bpe.extend([("cd", 0.5, TokenType::Normal)]);
+        assert!(bpe.inaccessible_tokens().is_empty());
+        assert!(bpe.internal_special().into_iter().all(|(_, t)| t != 9));
+        assert_eq!(bpe.encode("bcd").into_iter().collect::<Vec<_>>(), [9]);
+        assert_eq!(crate::Tokeneer::new(bpe).encode("dbcd"), [4, 9]);
+    }
+
+    #[test]
+    fn test_bpe_verify_byte_coverage() {
+        let pieces = (0..=u8::MAX)
+            .filter(|&b| b != 0x80)
+            .map(|b| format!("<0x{b:02X}>"))
+            .collect::<Vec<_>>();
+        let vocabs = std::iter::once("<unk>").chain(pieces.iter().map(String::as_str));
+        let types = std::iter::once(TokenType::Unknown)
+            .chain(std::iter::repeat_n(TokenType::Byte, pieces.len()));
+        let bpe = Bpe::new(vocabs, vec![0.; 256], types, 0);
+        assert_eq!(bpe.verify_byte_coverage(), Err(vec![0x80]));
+
+        let bpe = test_bpe();
+        assert_eq!(bpe.verify_byte_coverage().unwrap_err().len(), 256);
+    }
+
+    #[test]
+    fn test_bpe_decode_borrowed() {
+        let bpe = test_bpe();
+        let range = bpe._vocabs.as_ptr_range();
+        for t in 0..bpe.vocab_size() as utok {
+            let piece = bpe.decode(t);
+            assert!(range.contains(&piece.as_ptr()));
+            let (_, Cow::Borrowed(borrowed)) = bpe.iter_vocab().nth(t as _).unwrap() else {
+                panic!("iter_vocab should borrow from the vocab")
+            };
+            assert_eq!(borrowed.as_ptr(), piece.as_ptr())
+        }
+    }
+
+    #[test]
+    fn test_bpe_fingerprint() {
+        assert_eq!(test_bpe().fingerprint(), test_bpe().fingerprint());
+
+        let vocabs = ["<unk>", "a", "b", "c", "d", "ab", "ac", "ad", "bd", "bcd"];
+        let scores = [0., 1., 1., 1., 1., 1.1, 1.2, 1.3, 1.4, 10.];
+        let fingerprint = |vocabs: [&str; 10], scores: [f32; 10]| {
+            Bpe::new(vocabs, scores, [TokenType::Normal; 10], 0).fingerprint()
+        };
+        let base = fingerprint(vocabs, scores);
+        assert_eq!(base, test_bpe().fingerprint());
+
+        let mut modified = vocabs;
+        modified[9] = "bce";
+        assert_ne!(fingerprint(modified, scores), base);
+        let mut modified = scores;
+        modified[5] = 1.5;
+        assert_ne!(fingerprint(vocabs, modified), base);
+    }
+
+    #[test]
+    fn test_bpe_encode_bytes() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "ab", "<0xFF>", "<0xC3>"],
+            [0., 1., 1., 2., 0., 0.],
+            [
+                TokenType::Unknown,
+                TokenType::Normal,
+                TokenType::Normal,
+                TokenType::Normal,
+                TokenType::Byte,
+                TokenType::Byte,
+            ],
+            0,
+        );
+        assert_eq!(bpe.encode_bytes(b"ab\xffab\xc3"), [3, 4, 3, 5]);
+        assert_eq!(bpe.encode_bytes(b"ab\xfe"), [3, 0]);
+        assert_eq!(bpe.encode_bytes(b"ab"), [3]);
+    }
+
+    #[test]
+    fn test_hf_tokenizer_json_round_trip() {
+        let json = r#"{
+            "version": "1.0",
+            "added_tokens": [{"id": 9, "content": "<s>", "special": true}],
+            "model": {
+                "type": "BPE",
+                "unk_token": "<unk>",
+                "vocab": {
+                    "<unk>": 0, "a": 1, "b": 2, "c": 3, "d": 4,
+                    "ab": 5, "bc": 6, "abc": 7, "<0x0A>": 8, "acd": 10
+                },
+                "merges": ["b c", "a b", ["a", "bc"]]
+            }
+        }"#;
+        let bpe = Bpe::from_hf_tokenizer_json(json).unwrap();
+        assert_eq!(bpe.vocab_size(), 11);
+        assert_eq!(bpe.encode("abc").into_iter().collect::<Vec<_>>(), [7]);
+        assert_eq!(
+            bpe.encode("abd\n").into_iter().collect::<Vec<_>>(),
+            [5, 4, 8]
+        );
+
+        // "acd" 内部不可达，导出为非特殊的 added_tokens
+        assert!(bpe.inaccessible_tokens().contains(&10));
+        let exported = bpe.to_hf_tokenizer_json().unwrap();
+        assert!(exported.contains(r#"{"id":10,"content":"acd","special":false}"#));
+        assert!(exported.contains(r#""merges":[["b","c"],["a","b"],["a","bc"]]"#));
+        let reloaded = Bpe::from_hf_tokenizer_json(&exported).unwrap();
+        assert!(bpe.iter_vocab().eq(reloaded.iter_vocab()));
+        for text in ["abc", "abcabd", "bca\nb", "dcba", "<s>x"] {
+            assert!(
+                bpe.encode(text)
+                    .into_iter()
+                    .eq(reloaded.encode(text).into_iter())
+            );
+        }
+        assert_eq!(reloaded.to_hf_tokenizer_json().unwrap(), exported);
+
+        assert_eq!(
+            Bpe::from_hf_tokenizer_json(r#"{"model": {"vocab": {"a": 1}}}"#).err(),
+            Some(VocabError::InvalidPiece { index: 0 })
+        );
+        // 序号超过词的总数
+        assert_eq!(
+            Bpe::from_hf_tokenizer_json(r#"{"model": {"vocab": {"a": 0, "b": 4000000000}}}"#).err(),
+            Some(VocabError::InvalidPiece { index: 1 })
+        );
+        assert_eq!(
+            Bpe::from_hf_tokenizer_json(
+                r#"{"added_tokens": [{"id": 2, "content": "<s>"}], "model": {"vocab": {"a": 0}}}"#
+            )
+            .err(),
+            Some(VocabError::InvalidPiece { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_inaccessible_tokens() {
+        let bpe = test_bpe();
+        let inaccessible = bpe.inaccessible_tokens();
+        assert_eq!(inaccessible, [9]);
+        assert_eq!(bpe.token_piece_display(inaccessible[0]), "bcd");
+    }
+
+    #[test]
+    fn test_fallback_mode() {
+        let mut bpe = test_bpe();
+        let encode = |bpe: &Bpe| bpe.encode("ab中bd").into_iter().collect::<Vec<_>>();
+        let encode_z = |bpe: &Bpe| bpe.encode("abz").into_iter().collect::<Vec<_>>();
+        // 词表中没有单字节词，"中" 的 3 个字节都编码为 unk
+        assert_eq!(encode(&bpe), [5, 0, 0, 0, 8]);
+        bpe.set_fallback(FallbackMode::Unk);
+        assert_eq!(encode(&bpe), [5, 0, 8]);
+        bpe.set_fallback(FallbackMode::Skip);
+        assert_eq!(encode(&bpe), [5, 8]);
+        // 词表中没有的 ascii 字符同样无法匹配
+        assert_eq!(encode_z(&bpe), [5]);
+        bpe.set_fallback(FallbackMode::Unk);
+        assert_eq!(encode_z(&bpe), [5, 0]);
+        assert_eq!(bpe.encode_strict("abz").err().map(|e| e.range), Some(2..3));
+        bpe.set_fallback(FallbackMode::ByteFallback);
+        assert_eq!(encode_z(&bpe), [5, 0]);
+        bpe.set_fallback(FallbackMode::Skip);
+        bpe.extend([("abd", 1., TokenType::Normal)]);
+        assert_eq!(bpe.encode("中").into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let bpe = test_bpe();
+        let mut out = Vec::new();
+        let mut scratch = EncodeScratch::default();
+        for text in ["abcd", "bcdbcdbcd", "", "a中dbac", "dcba"] {
+            out.clear();
+            bpe.encode_into(text, &mut out, &mut scratch);
+            assert!(
+                bpe.encode(text).into_iter().eq(out.iter().copied()),
+                "{text}"
+            );
+        }
+        // 结果追加在已有内容之后
+        bpe.encode_into("ab", &mut out, &mut scratch);
+        assert_eq!(out.last(), Some(&5));
+    }
+
+    #[test]
+    fn test_token_histogram() {
+        let bpe = test_bpe();
+        // abd -> a bd，bcd 内部不可达 -> b c d，ac -> ac
+        let histogram = bpe.token_histogram(&["abd", "bcdabd", "ac", ""]);
+        assert_eq!(
+            histogram,
+            [(1, 2), (8, 2), (2, 1), (3, 1), (4, 1), (6, 1)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn test_decode_each() {
+        let bpe = test_bpe();
+        let tokens = bpe.encode("abdac").into_iter().collect::<Vec<_>>();
+        assert_eq!(bpe.decode_each(&tokens), ["a", "bd", "ac"]);
+    }
+
+    #[test]
+    fn test_rank_tie_break() {
+        assert_eq!(
+            rank(&[1., 2., 1., 3., 2.], ScoreOrder::HigherFirst)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [3, 1, 4, 0, 2]
+        );
+
+        // 评分相同的合并按序号决定先后，多次构造的编码结果一致
+        let build = || {
+            Bpe::new(
+                ["<unk>", "a", "b", "c", "ab", "bc"],
+                [0., 0., 0., 0., 1., 1.],
+                [TokenType::Normal; 6],
+                0,
+            )
+        };
+        let expected = build().encode("abc").into_iter().collect::<Vec<_>>();
+        assert_eq!(expected, [4, 3]);
+        for _ in 0..8 {
+            assert_eq!(
+                build().encode("abc").into_iter().collect::<Vec<_>>(),
+                expected
+            )
+        }
+    }
+
+    #[test]
+    fn test_contains_piece() {
+        let bpe = test_bpe();
+        assert!(bpe.contains_piece(b"bcd"));
+        assert!(bpe.contains_piece(b"a"));
+        assert!(!bpe.contains_piece(b"abc"));
+        assert!(!bpe.contains_piece(b"z"));
+        assert!(!bpe.contains_piece(b"<unk>"));
+
+        // 单字节词只能通过字节表找到
+        let bpe = Bpe::new(
+            ["<unk>", "<0xE4>", "中"],
+            [0.; 3],
+            [TokenType::Unknown, TokenType::Byte, TokenType::Normal],
+            0,
+        );
+        assert!(bpe.contains_piece(&[0xe4]));
+        assert!(bpe.contains_piece("中".as_bytes()));
+        assert!(!bpe.contains_piece(&[0xe5]));
+    }
+
+    #[test]
+    fn test_merge_overrides() {
+        let mut bpe = test_bpe();
+        assert_eq!(bpe.encode("abd").into_iter().collect::<Vec<_>>(), [1, 8]);
+        // 强制 a + b 先于 b + d 合并
+        bpe.with_merge_overrides(HashMap::from([((1, 2), 0)]));
+        assert_eq!(bpe.encode("abd").into_iter().collect::<Vec<_>>(), [5, 4]);
+        // 其他合并不受影响
+        assert_eq!(bpe.encode("bcd").into_iter().collect::<Vec<_>>(), [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_merge_cap() {
+        // 总是成功的合并在达到上限后停止
+        let mut count = 0;
+        assert!(!algorithm::merge_capped(
+            || {
+                count += 1;
+                true
+            },
+            5
+        ));
+        assert_eq!(count, 6);
+
+        let mut count = 0;
+        assert!(algorithm::merge_capped(
+            || {
+                count += 1;
+                count <= 5
+            },
+            5
+        ));
+
+        // 正常的编码不会触及上限
+        let bpe = test_bpe();
+        let text = "abdbcdac";
+        let mut merges = bpe.begin_merge(text);
+        assert!(algorithm::merge_capped(|| merges.merge(), text.len()));
+        assert_eq!(
+            bpe.encode(text).into_iter().collect::<Vec<_>>(),
+            [1, 8, 2, 3, 4, 6]
+        );
+    }
+
+    #[test]
+    fn test_score_order() {
+        let vocabs = ["<unk>", "a", "b", "c", "ab", "bc"];
+        let scores = [0., 0., 0., 0., 1., 2.];
+        let build =
+            |order| Bpe::new_with_score_order(vocabs, scores, [TokenType::Normal; 6], 0, order);
+        // 高分先合并：bc 先于 ab
+        let bpe = build(ScoreOrder::HigherFirst);
+        assert_eq!(bpe.encode("abc").into_iter().collect::<Vec<_>>(), [1, 5]);
+        assert_eq!(
+            bpe.encode("abc").into_iter().collect::<Vec<_>>(),
+            Bpe::new(vocabs, scores, [TokenType::Normal; 6], 0)
+                .encode("abc")
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        // 低分先合并：ab 先于 bc
+        let mut bpe = build(ScoreOrder::LowerFirst);
+        assert_eq!(bpe.encode("abc").into_iter().collect::<Vec<_>>(), [4, 3]);
+        // 追加新词沿用评分顺序
+        bpe.extend(
+            [("x", 0.), ("y", 0.), ("z", 0.), ("xy", 1.), ("yz", 2.)]
+                .map(|(piece, score)| (piece, score, TokenType::Normal)),
+        );
+        assert_eq!(bpe.encode("xyz").into_iter().collect::<Vec<_>>(), [9, 8]);
+    }
+
+    #[test]
+    fn test_self_consistency_report() {
+        let bpe = test_bpe();
+        // unk 和不可达的 bcd
+        assert_eq!(bpe.self_consistency_report(), [0, 9]);
+        // 内容重复的词只有一个能被编码得到
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "ab", "ab"],
+            [0., 0., 0., 1., 1.],
+            [
+                TokenType::Unknown,
+                TokenType::Normal,
+                TokenType::Normal,
+                TokenType::Normal,
+                TokenType::Normal,
+            ],
+            0,
+        );
+        let report = bpe.self_consistency_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0], 0);
+        assert!(report[1] == 3 || report[1] == 4);
+    }
+
+    #[test]
+    fn test_export_merges() {
+        let pairs = |bpe: &Bpe| {
+            bpe.export_merges()
+                .into_iter()
+                .map(|(l, r)| (String::from_utf8(l).unwrap(), String::from_utf8(r).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let merges = pairs(&test_bpe());
+        // bcd 不能拆分为两个已有词
+        assert!(merges.iter().all(|(l, r)| format!("{l}{r}") != "bcd"));
+        assert_eq!(
+            merges,
+            [("b", "d"), ("a", "d"), ("a", "c"), ("a", "b")].map(|(l, r)| (l.into(), r.into()))
+        );
+
+        // bcd 有两种拆分，bc 先于 cd 合并，因此由 bc 和 d 合并得到
+        let bpe = Bpe::new(
+            ["<unk>", "b", "c", "d", "bc", "cd", "bcd"],
+            [0., 0., 0., 0., 3., 2., 1.],
+            [TokenType::Normal; 7],
+            0,
+        );
+        assert_eq!(
+            pairs(&bpe),
+            [("b", "c"), ("c", "d"), ("bc", "d")].map(|(l, r)| (l.into(), r.into()))
+        );
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        let bpe = test_bpe();
+        for t in 0..bpe.vocab_size() as utok {
+            assert_eq!(bpe.decoded_len(t), bpe.decode(t).len())
+        }
+    }
+
+    #[test]
+    fn test_encode_restricted() {
+        let bpe = test_bpe();
+        let text = "abcdbcd";
+        let all = (0..bpe.vocab_size() as utok).collect::<HashSet<_>>();
+        assert_eq!(
+            bpe.encode_restricted(text, &all),
+            bpe.encode(text).into_iter().collect::<Vec<_>>()
+        );
+
+        // 不允许 bd 时只能使用其他的合并
+        let text = "abdad";
+        assert_eq!(bpe.encode(text).into_iter().collect::<Vec<_>>(), [1, 8, 7]);
+        let allowed = HashSet::from([1, 2, 3, 4, 5, 7]);
+        let tokens = bpe.encode_restricted(text, &allowed);
+        assert!(tokens.iter().all(|t| allowed.contains(t)));
+        assert_eq!(tokens, [5, 4, 7]);
+        assert_eq!(
+            tokens
+                .iter()
+                .flat_map(|&t| bpe.decode(t))
+                .copied()
+                .collect::<Vec<_>>(),
+            text.as_bytes()
+        );
+        // 不允许的字符在没有可用的单字节词时为 unk
+        assert_eq!(bpe.encode_restricted("ad", &HashSet::from([1])), [1, 0]);
+    }
+
+    #[test]
+    fn test_vocab_blob() {
+        let bpe = test_bpe();
+        let blob = bpe.vocab_blob();
+        for t in 0..bpe.vocab_size() as utok {
+            let (off, len) = bpe.token_offset(t);
+            assert_eq!(&blob[off..][..len], bpe.decode(t))
+        }
+        // 共享存储的词表比所有词的内容之和短
+        let total = (0..bpe.vocab_size() as utok)
+            .map(|t| bpe.decode(t).len())
+            .sum::<usize>();
+        assert!(blob.len() < total)
+    }
+
+    #[test]
+    fn test_max_token_len() {
+        let bpe = test_bpe();
+        // <unk> 的内容最长
+        assert_eq!(bpe.max_token_len(), 5);
+        let bpe = Bpe::new(["a", "bcd", "ab"], [0.; 3], [TokenType::Normal; 3], 0);
+        assert_eq!(bpe.max_token_len(), 3);
+    }
+
+    #[test]
+    fn test_bytes_to_tokens() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "ab", "<0x62>", "<0xFF>"],
+            [0.; 5],
+            [
+                TokenType::Unknown,
+                TokenType::Normal,
+                TokenType::Normal,
+                TokenType::Byte,
+                TokenType::Byte,
+            ],
+            0,
+        );
+        // 不合并 ab，没有单字节词的字节为 unk，普通词 a 不是单字节词
+        assert_eq!(bpe.bytes_to_tokens(b"ab\xff\xfe"), [0, 3, 4, 0]);
+        assert_eq!(bpe.bytes_to_tokens(b""), [] as [utok; 0]);
+    }
 }
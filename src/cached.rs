@@ -0,0 +1,203 @@
+//! 缓存整段输入的编码结果。
+
+use crate::{Method, utok};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Mutex,
+};
+
+/// 记住最近编码过的完整输入的适配器，适合反复编码相同系统提示词的服务。
+///
+/// 缓存以整段输入为单位，与 BPE 会话中按预分词片段的缓存相互独立。
+pub struct CachedMethod<M> {
+    method: M,
+    cache: Mutex<EncodeCache>,
+}
+
+/// 有界的 LRU 缓存，以输入的哈希为键，保存原文以排除哈希冲突
+struct EncodeCache {
+    capacity: usize,
+    /// 输入的哈希 -> (输入, 编码结果, 最近访问时刻)
+    map: HashMap<u64, (Box<str>, Vec<utok>, u64)>,
+    /// 访问时刻 -> 输入的哈希，最小的时刻最久未使用
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    hits: usize,
+}
+
+impl EncodeCache {
+    fn get(&mut self, key: u64, text: &str) -> Option<Vec<utok>> {
+        let (cached, tokens, last) = self.map.get_mut(&key)?;
+        if &**cached != text {
+            return None;
+        }
+        self.order.remove(last);
+        self.tick += 1;
+        *last = self.tick;
+        self.order.insert(self.tick, key);
+        self.hits += 1;
+        Some(tokens.clone())
+    }
+
+    fn put(&mut self, key: u64, text: &str, tokens: Vec<utok>) {
+        if let Some((_, _, last)) = self.map.remove(&key) {
+            self.order.remove(&last);
+        } else if self.map.len() >= self.capacity
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            self.map.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key);
+        self.map.insert(key, (text.into(), tokens, self.tick));
+    }
+}
+
+impl<M> CachedMethod<M> {
+    /// 包装分词器，至多缓存 `capacity` 个输入，容量为 0 时不缓存
+    pub fn new(method: M, capacity: usize) -> Self {
+        Self {
+            method,
+            cache: Mutex::new(EncodeCache {
+                capacity,
+                map: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                hits: 0,
+            }),
+        }
+    }
+
+    /// 命中缓存的次数
+    pub fn hits(&self) -> usize {
+        self.cache.lock().unwrap().hits
+    }
+
+    /// 当前缓存的输入数
+    pub fn cached_len(&self) -> usize {
+        self.cache.lock().unwrap().map.len()
+    }
+
+    /// 清空缓存和命中计数
+    pub fn clear(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.map.clear();
+        cache.order.clear();
+        cache.hits = 0
+    }
+
+    #[inline]
+    pub fn internal(&self) -> &M {
+        &self.method
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.method
+    }
+}
+
+impl<M: Method> Method for CachedMethod<M> {
+    #[inline]
+    fn unk_token(&self) -> utok {
+        self.method.unk_token()
+    }
+    #[inline]
+    fn vocab_size(&self) -> usize {
+        self.method.vocab_size()
+    }
+    #[inline]
+    fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)> {
+        self.method.internal_special()
+    }
+    fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.capacity == 0 {
+            drop(cache);
+            return self.method.encode(text).into_iter().collect::<Vec<_>>();
+        }
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(tokens) = cache.get(key, text) {
+            return tokens;
+        }
+        // 编码时不持有锁，其他线程可以同时查询缓存
+        drop(cache);
+        let tokens = self.method.encode(text).into_iter().collect::<Vec<_>>();
+        self.cache.lock().unwrap().put(key, text, tokens.clone());
+        tokens
+    }
+    #[inline]
+    fn decode(&self, token: utok) -> &[u8] {
+        self.method.decode(token)
+    }
+    #[inline]
+    fn decoded_len(&self, token: utok) -> usize {
+        self.method.decoded_len(token)
+    }
+    #[inline]
+    fn token_of(&self, piece: &str) -> Option<utok> {
+        self.method.token_of(piece)
+    }
+    #[inline]
+    fn iter_vocab(&self) -> impl Iterator<Item = (utok, Cow<'_, [u8]>)> + '_ {
+        self.method.iter_vocab()
+    }
+    fn encode_bytes(&self, bytes: &[u8]) -> Vec<utok> {
+        self.method.encode_bytes(bytes)
+    }
+    fn pre_decode<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        self.method.pre_decode(bytes)
+    }
+    fn encode_with_special(&self, text: &str, add_bos: bool, add_eos: bool) -> Vec<utok> {
+        self.method.encode_with_special(text, add_bos, add_eos)
+    }
+}
+
+#[cfg(test)]
+mod cached_tests {
+    use super::*;
+    use crate::{Bpe, TokenType};
+
+    fn encode(method: &impl Method, text: &str) -> Vec<utok> {
+        method.encode(text).into_iter().collect()
+    }
+
+    #[test]
+    fn test_cached_method() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "ab"],
+            [0., -1., -1., 0.],
+            [TokenType::Normal; 4],
+            0,
+        );
+        let expected = encode(&bpe, "abab");
+        let cached = CachedMethod::new(bpe, 2);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!(cached.hits(), 0);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!(cached.hits(), 1);
+
+        // 超过容量时淘汰最久未使用的输入
+        assert_eq!(encode(&cached, "a"), [1]);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!(encode(&cached, "b"), [2]);
+        assert_eq!(cached.cached_len(), 2);
+        assert_eq!(encode(&cached, "a"), [1]);
+        assert_eq!(cached.hits(), 2);
+
+        cached.clear();
+        assert_eq!(cached.cached_len(), 0);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!(cached.hits(), 0);
+
+        // 容量为 0 时不缓存
+        let cached = CachedMethod::new(cached.into_inner(), 0);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!(encode(&cached, "abab"), expected);
+        assert_eq!((cached.hits(), cached.cached_len()), (0, 0));
+    }
+}
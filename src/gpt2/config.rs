@@ -0,0 +1,135 @@
+//! 分词器的特殊标记与行为开关。
+
+use super::common::{NULL, TokenAttribute, TokenId};
+
+/// 特殊标记的序号，不存在的标记为 `u32::MAX`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpecialTokens {
+    pub bos: TokenId,
+    pub eos: TokenId,
+    pub eot: TokenId,
+    pub eom: TokenId,
+    pub unk: TokenId,
+    pub sep: TokenId,
+    pub pad: TokenId,
+    pub fim_pre: TokenId,
+    pub fim_suf: TokenId,
+    pub fim_mid: TokenId,
+    pub fim_pad: TokenId,
+    pub fim_rep: TokenId,
+    pub fim_sep: TokenId,
+    pub linefeed: TokenId,
+    pub mask: TokenId,
+}
+
+impl Default for SpecialTokens {
+    fn default() -> Self {
+        Self {
+            bos: 1,
+            eos: 2,
+            eot: NULL,
+            eom: NULL,
+            unk: 0,
+            sep: NULL,
+            pad: NULL,
+            fim_pre: NULL,
+            fim_suf: NULL,
+            fim_mid: NULL,
+            fim_pad: NULL,
+            fim_rep: NULL,
+            fim_sep: NULL,
+            linefeed: NULL,
+            mask: NULL,
+        }
+    }
+}
+
+/// 分词时识别文本中哪些特殊标记
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpecialParseMode {
+    /// 识别所有特殊标记
+    All,
+    /// 只识别用户定义等非控制标记，不识别控制标记和未知标记
+    UserDefined,
+    /// 只识别控制标记和未知标记
+    Control,
+    /// 不识别任何特殊标记
+    None,
+}
+
+impl From<bool> for SpecialParseMode {
+    /// 与 `parse_special` 开关对应：`true` 识别所有特殊标记，`false` 只识别非控制标记
+    fn from(parse_special: bool) -> Self {
+        if parse_special {
+            Self::All
+        } else {
+            Self::UserDefined
+        }
+    }
+}
+
+impl SpecialParseMode {
+    /// 是否识别具有 `attribute` 属性的特殊标记
+    pub(super) fn parses(self, attribute: TokenAttribute) -> bool {
+        let control = matches!(attribute, TokenAttribute::Control | TokenAttribute::Unknown);
+        match self {
+            Self::All => true,
+            Self::UserDefined => !control,
+            Self::Control => control,
+            Self::None => false,
+        }
+    }
+}
+
+/// 文本规范化和分词行为的开关
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NormalizationFlags {
+    pub add_space_prefix: bool,
+    pub add_bos: bool,
+    pub add_eos: bool,
+    pub ignore_merges: bool,
+    pub clean_spaces: bool,
+    pub remove_extra_whitespaces: bool,
+    pub escape_whitespaces: bool,
+    pub treat_whitespace_as_suffix: bool,
+    /// `encode` 是否识别文本中的控制标记，默认识别
+    pub parse_special: bool,
+    /// 识别特殊标记时忽略 ascii 字母的大小写，默认精确匹配
+    pub special_case_insensitive: bool,
+    /// 分词前将 `\r\n` 转换为 `\n`，默认关闭
+    pub normalize_newlines: bool,
+    /// 分词前去掉文本开头的 utf-8 BOM（U+FEFF），默认关闭
+    pub strip_bom: bool,
+    /// 分词前去掉零宽空格、零宽连接符和零宽非连接符，默认关闭
+    pub strip_zero_width: bool,
+    /// 分词前将全角 ascii 字符和全角空格转换为半角，默认关闭
+    pub fullwidth_to_halfwidth: bool,
+}
+
+impl Default for NormalizationFlags {
+    fn default() -> Self {
+        Self {
+            add_space_prefix: false,
+            add_bos: true,
+            add_eos: false,
+            ignore_merges: false,
+            clean_spaces: false,
+            remove_extra_whitespaces: false,
+            escape_whitespaces: true,
+            treat_whitespace_as_suffix: false,
+            parse_special: true,
+            special_case_insensitive: false,
+            normalize_newlines: false,
+            strip_bom: false,
+            strip_zero_width: false,
+            fullwidth_to_halfwidth: false,
+        }
+    }
+}
+
+/// [`Gpt2Tokenizer`](super::Gpt2Tokenizer) 的配置
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Gpt2Config {
+    pub specials: SpecialTokens,
+    pub flags: NormalizationFlags,
+}
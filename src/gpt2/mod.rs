@@ -1,34 +1,107 @@
 mod common;
+mod config;
 mod session;
+mod stream;
 mod unicode;
+mod unicode_data;
 mod untils;
 use std::{
+    borrow::Cow,
     cell::RefCell,
     collections::{HashMap, HashSet, LinkedList},
+    ops::Range,
 };
 
-use common::{NULL, QWEN, TokenAttribute, TokenData, TokenId};
-use ggus::{GGuf, GGufMetaError, GGufMetaMapExt};
+use common::{NULL, TokenAttribute, TokenData, TokenId};
+use ggus::{GGuf, GGufMetaError, GGufMetaMapExt, GGufReadError};
 use memmap2::Mmap;
-use session::{LlmTokenizerBpe, LlmTokenizerBpeSession};
-use unicode::{unicode_byte_to_utf8, unicode_byte_to_utf8_map};
-use untils::llama_escape_whitespace;
+use session::{LlmTokenizerBpe, LlmTokenizerBpeSession, LlmTokenizerSpmSession};
+
+pub use config::{Gpt2Config, NormalizationFlags, SpecialParseMode, SpecialTokens};
+pub use session::{PretokenizeKind, SessionStats};
+pub use stream::DecodeStream;
+use unicode::{unicode_byte_decoding, unicode_byte_to_utf8, unicode_byte_to_utf8_map};
+use untils::{META_SPACE, llama_clean_spaces, llama_escape_whitespace, llama_unescape_whitespace};
 
 use crate::Method;
 
-fn load_gpt2<T: GGufMetaMapExt>(gguf: &T) -> HashMap<(String, String), usize> {
+fn load_gpt2<T: GGufMetaMapExt>(gguf: &T) -> Result<HashMap<(String, String), usize>, Gpt2Error> {
     gguf.tokenizer_ggml_merges()
-        .unwrap()
-        .map(|x| {
-            let piece = x.unwrap();
-            let (first, second) = piece.split_once(' ').unwrap();
-            (first.to_string(), second.to_string())
-        })
+        .map_err(|e| Gpt2Error::missing(e, Gpt2Error::MissingMerges))?
         .enumerate()
-        .map(|(i, pair)| (pair, i))
+        .map(|(i, x)| {
+            let piece = x.map_err(Gpt2Error::read)?;
+            let (first, second) = piece
+                .split_once(' ')
+                .ok_or_else(|| Gpt2Error::InvalidMerge(piece.into()))?;
+            Ok(((first.to_string(), second.to_string()), i))
+        })
         .collect()
 }
 
+/// 规范化时去掉的零宽字符
+const ZERO_WIDTH: [char; 3] = ['\u{200B}', '\u{200C}', '\u{200D}'];
+
+/// 全角 ascii 字符（U+FF01..=U+FF5E）和全角空格转换为对应的半角字符，与 NFKC 的结果相同
+fn halfwidth(c: char) -> char {
+    match c {
+        '\u{3000}' => ' ',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap(),
+        c => c,
+    }
+}
+
+/// 从 gguf 加载分词器失败的原因
+#[derive(Debug)]
+pub enum Gpt2Error {
+    /// 缺少 `tokenizer.ggml.tokens`
+    MissingTokens,
+    /// 缺少 `tokenizer.ggml.token_type`
+    MissingTokenType,
+    /// 缺少 `tokenizer.ggml.merges`
+    MissingMerges,
+    /// 合词规则不是以空格分隔的两个词
+    InvalidMerge(String),
+    /// `tokenizer.ggml.model` 不是支持的词表类型
+    UnsupportedVocab(String),
+    /// 这个键的数组长度与 `tokenizer.ggml.tokens` 不同
+    LengthMismatch(&'static str),
+    /// 元信息的类型或内容错误
+    Meta(GGufMetaError),
+}
+
+impl Gpt2Error {
+    /// 元信息不存在时转换为 `missing`，其他错误原样保留
+    fn missing(e: GGufMetaError, missing: Self) -> Self {
+        match e {
+            GGufMetaError::NotExist => missing,
+            e => Self::Meta(e),
+        }
+    }
+
+    fn read(e: GGufReadError) -> Self {
+        Self::Meta(GGufMetaError::Read(e))
+    }
+}
+
+impl std::fmt::Display for Gpt2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingTokens => write!(f, "missing tokenizer.ggml.tokens"),
+            Self::MissingTokenType => write!(f, "missing tokenizer.ggml.token_type"),
+            Self::MissingMerges => write!(f, "missing tokenizer.ggml.merges"),
+            Self::InvalidMerge(merge) => write!(f, "invalid merge {merge:?}"),
+            Self::UnsupportedVocab(model) => write!(f, "unsupported tokenizer model: {model}"),
+            Self::LengthMismatch(key) => {
+                write!(f, "{key} length mismatch with tokenizer.ggml.tokens")
+            }
+            Self::Meta(e) => write!(f, "invalid gguf metadata: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Gpt2Error {}
+
 #[repr(i32)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum VocabType {
@@ -40,110 +113,193 @@ pub enum VocabType {
     Rwkv = 5, // RWKV tokenizer based on greedy tokenization
 }
 
+impl VocabType {
+    /// 由 `tokenizer.ggml.model` 的值确定词表类型，不支持的值返回 `None`
+    pub fn from_model(model: &str) -> Option<Self> {
+        match model {
+            "no_vocab" => Some(Self::None),
+            "llama" => Some(Self::Spm),
+            "gpt2" => Some(Self::Bpe),
+            "bert" => Some(Self::Wpm),
+            "t5" => Some(Self::Ugm),
+            "rwkv" => Some(Self::Rwkv),
+            _ => None,
+        }
+    }
+}
+
+/// 对话模板中的一段内容
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Segment<'a> {
+    /// 普通文本，其中的特殊标记文本不会被识别
+    Text(&'a str),
+    /// 直接输出的特殊标记
+    Special(TokenId),
+}
+
+/// 特殊标记切分的结果，见 [`Gpt2Tokenizer::segment`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Fragment {
+    /// 普通文本在原文中的字节范围
+    Text(Range<usize>),
+    /// 文本中识别出的特殊标记
+    Special(TokenId),
+}
+
 pub struct Gpt2Tokenizer {
     pub vocab_type: VocabType,
-    pub bos: u32,
-    pub eos: u32,
-    pub eot: u32,
-    pub eom: u32,
-    pub unk: u32,
-    pub sep: u32,
-    pub pad: u32,
-    pub fim_pre: u32,
-    pub fim_suf: u32,
-    pub fim_mid: u32,
-    pub fim_pad: u32,
-    pub fim_rep: u32,
-    pub fim_sep: u32,
-    pub linefeed: u32,
-    pub mask: u32,
-    pub add_space_prefix: bool,
-    pub add_bos: bool,
-    pub add_eos: bool,
-    pub ignore_merges: bool,
-    pub clean_spaces: bool,
-    pub remove_extra_whitespaces: bool,
-    pub escape_whitespaces: bool,
-    pub treat_whitespace_as_suffix: bool,
+    /// 特殊标记与行为开关
+    pub config: Gpt2Config,
+    /// SPM 中代替空格的字符
+    pub meta_space: char,
     pub token_to_id: HashMap<String, TokenId>,
     pub special_tokens: Vec<TokenId>,
+    /// 结束生成的标记，按 id 升序排列
+    pub eog_tokens: Vec<TokenId>,
     pub id_to_token: Vec<TokenData>,
     pub bpe_ranks: HashMap<(String, String), usize>,
     pub session: RefCell<LlmTokenizerBpeSession>,
     pub char_hash: HashMap<u8, char>,
     pub build: RefCell<String>,
 }
+/// 为配置中的字段生成同名的只读访问器，兼容原先的公开字段
+macro_rules! config_accessors {
+    ($group:ident: $ty:ty => $($name:ident),+) => {
+        impl Gpt2Tokenizer {
+            $(
+                #[inline]
+                pub fn $name(&self) -> $ty {
+                    self.config.$group.$name
+                }
+            )+
+        }
+    };
+}
+
+config_accessors!(specials: TokenId =>
+    bos, eos, eot, eom, unk, sep, pad, fim_pre, fim_suf, fim_mid, fim_pad, fim_rep, fim_sep,
+    linefeed, mask);
+config_accessors!(flags: bool =>
+    add_space_prefix, add_bos, add_eos, ignore_merges, clean_spaces, remove_extra_whitespaces,
+    escape_whitespaces, treat_whitespace_as_suffix, parse_special, special_case_insensitive, normalize_newlines,
+    strip_bom, strip_zero_width, fullwidth_to_halfwidth);
+
 impl Gpt2Tokenizer {
+    /// 由配置创建一个空词表的分词器
+    pub fn with_config(config: Gpt2Config) -> Self {
+        Self {
+            config,
+            ..Self::new()
+        }
+    }
+
     pub fn new() -> Self {
         Self {
             vocab_type: VocabType::None,
-            bos: 1,
-            eos: 2,
-            eot: NULL,
-            eom: NULL,
-            unk: 0,
-            sep: NULL,
-            pad: NULL,
-            fim_pre: NULL,
-            fim_suf: NULL,
-            fim_mid: NULL,
-            fim_pad: NULL,
-            fim_rep: NULL,
-            fim_sep: NULL,
-            linefeed: NULL,
-            mask: NULL,
-            add_space_prefix: false,
-            add_bos: true,
-            add_eos: false,
-            ignore_merges: false,
-            clean_spaces: false,
-            remove_extra_whitespaces: false,
-            escape_whitespaces: true,
-            treat_whitespace_as_suffix: false,
+            config: Gpt2Config::default(),
+            meta_space: META_SPACE,
             token_to_id: HashMap::new(),
             special_tokens: Vec::new(),
+            eog_tokens: Vec::new(),
             id_to_token: Vec::new(),
             bpe_ranks: HashMap::new(),
-            session: LlmTokenizerBpeSession::new(LlmTokenizerBpe {
-                // qwen
-                regex_exprs: vec![QWEN.to_string()],
-            })
-            .into(),
+            session: LlmTokenizerBpeSession::new(LlmTokenizerBpe::new(PretokenizeKind::Qwen))
+                .into(),
             char_hash: unicode_byte_to_utf8_map(),
             build: String::with_capacity(30).into(),
         }
     }
 
-    //  load 函数 默认都是gpt2
-    pub fn load_gguf<T: GGufMetaMapExt>(gguf: &T) -> Gpt2Tokenizer {
-        // 添加多模型支持需要根据 tokenizer_ggml_mode 和tokenizer.ggml.pre对词表进行不同的初始化
+    /// 与 [`Gpt2Tokenizer::load_gguf`] 相同，但 `tokenizer.ggml.model` 缺失时也返回错误
+    pub fn try_load_gguf<T: GGufMetaMapExt>(gguf: &T) -> Result<Gpt2Tokenizer, Gpt2Error> {
+        gguf.tokenizer_ggml_model().map_err(Gpt2Error::Meta)?;
+        Self::load_gguf(gguf)
+    }
+
+    /// 从 gguf 元信息加载分词器，按 `tokenizer.ggml.model` 确定词表类型，缺少时视为 gpt2。
+    ///
+    /// 缺少词表、词类型或 BPE 词表的合词规则，或词表类型不受支持时返回错误。
+    pub fn load_gguf<T: GGufMetaMapExt>(gguf: &T) -> Result<Gpt2Tokenizer, Gpt2Error> {
+        let vocab_type = match gguf.tokenizer_ggml_model() {
+            // WPM、UGM 与 RWKV 词表的分词尚未实现
+            Ok(model) => VocabType::from_model(model)
+                .filter(|t| !matches!(t, VocabType::Wpm | VocabType::Ugm | VocabType::Rwkv))
+                .ok_or_else(|| Gpt2Error::UnsupportedVocab(model.into()))?,
+            Err(GGufMetaError::NotExist) => VocabType::Bpe,
+            Err(e) => return Err(Gpt2Error::Meta(e)),
+        };
 
-        let mut config = Gpt2Tokenizer::new();
+        let mut tokenizer = Gpt2Tokenizer::new();
+        tokenizer.vocab_type = vocab_type;
+        let Gpt2Config { specials, flags } = &mut tokenizer.config;
+
+        // 按词表类型设置预设字段，与 llama.cpp 的默认值相同
+        specials.sep = NULL;
+        specials.pad = NULL;
+        specials.mask = NULL;
+        flags.add_space_prefix = false;
+        match vocab_type {
+            VocabType::Spm => {
+                specials.bos = 1;
+                specials.eos = 2;
+                specials.unk = 0;
+                flags.add_space_prefix = true;
+            }
+            VocabType::Wpm => {
+                specials.bos = 101;
+                specials.eos = NULL;
+                specials.unk = 100;
+                specials.sep = 102;
+                specials.pad = 0;
+                specials.mask = 103;
+            }
+            VocabType::Ugm => {
+                specials.bos = NULL;
+                specials.eos = 1;
+                specials.unk = 2;
+                specials.pad = 0;
+            }
+            VocabType::Rwkv | VocabType::None => {
+                specials.bos = NULL;
+                specials.eos = NULL;
+                specials.unk = NULL;
+            }
+            VocabType::Bpe => {
+                // gpt2 默认填充规则  LLAMA_VOCAB_PRE_TYPE_GPT2
+                specials.bos = 11;
+                specials.eos = 11;
+                specials.unk = NULL;
+                flags.clean_spaces = true;
+            }
+        }
 
-        // 设置预设字段
-        config.bos = 11;
-        config.eos = 11;
-        config.unk = NULL;
-        config.sep = NULL;
-        config.pad = NULL;
-        config.mask = NULL;
-        // bpe 需要预填充数据，设置字段
-        config.add_space_prefix = false;
-        config.clean_spaces = true;
-        // gpt2 默认填充规则  LLAMA_VOCAB_PRE_TYPE_GPT2
-        config.vocab_type = VocabType::Bpe;
-        // 检查是是否有填充字段，
+        // 根据 tokenizer.ggml.pre 选择预分词规则
+        if let Ok(pre) = gguf.get_str("tokenizer.ggml.pre") {
+            tokenizer.session =
+                LlmTokenizerBpeSession::new(LlmTokenizerBpe::new(PretokenizeKind::from_pre(pre)))
+                    .into();
+        }
 
         // 加载特殊字符
         {
             // SPM进行分词需要
-            config.add_space_prefix = gguf
+            flags.add_space_prefix = gguf
                 .get_bool("tokenizer.ggml.add_space_prefix")
-                .unwrap_or(false);
+                .unwrap_or(flags.add_space_prefix);
             // remove_extra_whitespaces
-            config.remove_extra_whitespaces = gguf
+            flags.remove_extra_whitespaces = gguf
                 .get_bool("tokenizer.ggml.remove_extra_whitespaces")
                 .unwrap_or(false);
+            flags.treat_whitespace_as_suffix = gguf
+                .get_bool("tokenizer.ggml.treat_whitespace_as_suffix")
+                .unwrap_or(false);
+            if let Some(c) = gguf
+                .get_str("tokenizer.ggml.meta_space")
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                tokenizer.meta_space = c
+            }
 
             let matche_token = |token: Result<u32, GGufMetaError>, target: u32| -> u32 {
                 if token.is_ok() {
@@ -152,68 +308,89 @@ impl Gpt2Tokenizer {
                     target
                 }
             };
-            config.bos = matche_token(gguf.tokenizer_ggml_bos_token_id(), config.bos);
-            config.eos = matche_token(gguf.tokenizer_ggml_eos_token_id(), config.eos);
-            config.eot = matche_token(gguf.get_u32("tokenizer.ggml.eot_token_id"), config.eot);
-            config.eom = matche_token(gguf.get_u32("tokenizer.ggml.eom_token_id"), config.eom);
-            config.unk = matche_token(gguf.get_u32("tokenizer.ggml.unknown_token_id"), config.unk);
-            config.sep = matche_token(
+            specials.bos = matche_token(gguf.tokenizer_ggml_bos_token_id(), specials.bos);
+            specials.eos = matche_token(gguf.tokenizer_ggml_eos_token_id(), specials.eos);
+            specials.eot = matche_token(gguf.get_u32("tokenizer.ggml.eot_token_id"), specials.eot);
+            specials.eom = matche_token(gguf.get_u32("tokenizer.ggml.eom_token_id"), specials.eom);
+            specials.unk = matche_token(
+                gguf.get_u32("tokenizer.ggml.unknown_token_id"),
+                specials.unk,
+            );
+            specials.sep = matche_token(
                 gguf.get_u32("tokenizer.ggml.seperator_token_id"),
-                config.sep,
+                specials.sep,
+            );
+            specials.pad = matche_token(
+                gguf.get_u32("tokenizer.ggml.padding_token_id"),
+                specials.pad,
             );
-            config.pad = matche_token(gguf.get_u32("tokenizer.ggml.padding_token_id"), config.pad);
-            config.mask = matche_token(gguf.get_u32("tokenizer.ggml.mask_token_id"), config.mask);
-            config.fim_pre = matche_token(
+            specials.mask =
+                matche_token(gguf.get_u32("tokenizer.ggml.mask_token_id"), specials.mask);
+            specials.fim_pre = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_pre_token_id"),
-                config.fim_pre,
+                specials.fim_pre,
             );
-            config.fim_suf = matche_token(
+            specials.fim_suf = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_suf_token_id"),
-                config.fim_suf,
+                specials.fim_suf,
             );
-            config.fim_mid = matche_token(
+            specials.fim_mid = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_mid_token_id"),
-                config.fim_mid,
+                specials.fim_mid,
             );
-            config.fim_pad = matche_token(
+            specials.fim_pad = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_pad_token_id"),
-                config.fim_pad,
+                specials.fim_pad,
             );
-            config.fim_rep = matche_token(
+            specials.fim_rep = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_rep_token_id"),
-                config.fim_rep,
+                specials.fim_rep,
             );
-            config.fim_sep = matche_token(
+            specials.fim_sep = matche_token(
                 gguf.get_u32("tokenizer.ggml.fim_sep_token_id"),
-                config.fim_sep,
+                specials.fim_sep,
             );
 
-            config.add_bos = gguf
+            flags.add_bos = gguf
                 .get_bool("tokenizer.ggml.add_bos_token")
-                .unwrap_or(config.add_bos);
-            config.add_eos = gguf
+                .unwrap_or(flags.add_bos);
+            flags.add_eos = gguf
                 .get_bool("tokenizer.ggml.add_eos_token")
-                .unwrap_or(config.add_eos);
+                .unwrap_or(flags.add_eos);
         }
 
-        let tokens = gguf.tokenizer_ggml_tokens().unwrap();
+        let tokens = gguf
+            .tokenizer_ggml_tokens()
+            .map_err(|e| Gpt2Error::missing(e, Gpt2Error::MissingTokens))?;
         let scores = gguf
             .tokenizer_ggml_scores()
             .ok()
-            .map(|arr| arr.map(|r| r.unwrap()).collect::<Vec<_>>());
+            .map(|arr| arr.collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(Gpt2Error::read)?;
         let token_type = gguf
             .tokenizer_ggml_token_type()
-            .ok()
-            .map(|arr| arr.map(|r| r.unwrap()).collect::<Vec<_>>())
-            .unwrap();
+            .map_err(|e| Gpt2Error::missing(e, Gpt2Error::MissingTokenType))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Gpt2Error::read)?;
         // 此处等同于llama.cpp的合并
-        let bpe_ranks = load_gpt2(gguf);
+        let bpe_ranks = if vocab_type == VocabType::Bpe {
+            load_gpt2(gguf)?
+        } else {
+            HashMap::new()
+        };
+        if token_type.len() != tokens.len() {
+            return Err(Gpt2Error::LengthMismatch("tokenizer.ggml.token_type"));
+        }
+        if scores.as_ref().is_some_and(|s| s.len() != tokens.len()) {
+            return Err(Gpt2Error::LengthMismatch("tokenizer.ggml.scores"));
+        }
         let mut id_to_token = Vec::with_capacity(tokens.len());
 
         let mut token_to_id: HashMap<String, TokenId> = HashMap::with_capacity(tokens.len());
 
         for (i, text) in tokens.into_iter().enumerate() {
-            let text = text.unwrap().to_string();
+            let text = text.map_err(Gpt2Error::read)?.to_string();
             let score = scores.as_ref().map_or(0.0, |s| s[i]);
             let attribute = match token_type[i] {
                 1 => TokenAttribute::Normal,
@@ -231,35 +408,49 @@ impl Gpt2Tokenizer {
                 attribute,
             });
 
-            token_to_id.insert(text, i as u32);
+            // 未使用的标记只能解码，不参与文本查找，因此不会被编码产生，也不会被识别为特殊标记
+            if !matches!(attribute, TokenAttribute::Unused) {
+                token_to_id.insert(text, i as u32);
+            }
         }
-        config.token_to_id = token_to_id.clone();
-        config.id_to_token = id_to_token.clone();
+        tokenizer.token_to_id = token_to_id.clone();
+        tokenizer.id_to_token = id_to_token.clone();
 
         // 待完善 linefeed_id 暂时不支持SPM  构造换行符
-        match config.vocab_type {
+        match tokenizer.vocab_type {
             VocabType::None | VocabType::Bpe => {
-                let ids = config.tokenize("\n", false, false);
+                let ids = tokenizer.tokenize("\n", false, false);
+                let specials = &mut tokenizer.config.specials;
                 if ids.is_empty() {
-                    config.linefeed = config.pad;
+                    specials.linefeed = specials.pad;
                 } else {
-                    config.linefeed = ids[0];
+                    specials.linefeed = ids[0];
                 }
             }
             VocabType::Spm => {
-                config.linefeed = if token_to_id.contains_key("\n") {
-                    *token_to_id.get("\n").unwrap()
+                // 优先使用 <0x0A> 字节词
+                let linefeed = tokenizer.try_byte_to_token(b'\n');
+                let specials = &mut tokenizer.config.specials;
+                specials.linefeed = linefeed.unwrap_or(specials.pad);
+            }
+            VocabType::Wpm => {
+                let specials = &mut tokenizer.config.specials;
+                specials.linefeed = specials.pad
+            }
+            VocabType::Ugm | VocabType::Rwkv => {
+                let linefeed = tokenizer.text_to_token("\n");
+                let specials = &mut tokenizer.config.specials;
+                specials.linefeed = if linefeed == NULL {
+                    specials.pad
                 } else {
-                    config.pad
+                    linefeed
                 };
             }
-            VocabType::Wpm => todo!(),
-            VocabType::Ugm => todo!(),
-            VocabType::Rwkv => todo!(),
         }
 
+        let specials = &mut tokenizer.config.specials;
         for (key, value) in &token_to_id {
-            if config.eot == NULL {
+            if specials.eot == NULL {
                 if key == "<|eot_id|>"
                     || key == "<|im_end|>"
                     || key == "<|end|>"
@@ -270,7 +461,7 @@ impl Gpt2Tokenizer {
                     || key == "<｜end▁of▁sentence｜>"
                 // DeepSeek
                 {
-                    config.eot = *value;
+                    specials.eot = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -279,9 +470,9 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.eom == NULL {
+            if specials.eom == NULL {
                 if key == "<|eom_id|>" {
-                    config.eom = *value;
+                    specials.eom = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -290,14 +481,15 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_pre == NULL {
+            if specials.fim_pre == NULL {
                 if key == "<|fim_prefix|>" // Qwen
                 || key == "<fim-prefix>"
+                || key == "<fim_prefix>" // StarCoder
                 || key == "<｜fim▁begin｜>" // DeepSeek
                 || key == "<PRE>"
                 || key == "▁<PRE>"
                 {
-                    config.fim_pre = *value;
+                    specials.fim_pre = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -306,15 +498,16 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_suf == NULL {
+            if specials.fim_suf == NULL {
                 if key == "<|fim_suffix|>" // Qwen
             || key == "<fim-suffix>"
+            || key == "<fim_suffix>" // StarCoder
             || key == "<｜fim▁hole｜>" // DeepSeek
             || key == "<SUF>"
             || key == "▁<SUF>"
                 // CodeLlama
                 {
-                    config.fim_suf = *value;
+                    specials.fim_suf = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -323,15 +516,16 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_mid == NULL {
+            if specials.fim_mid == NULL {
                 if key == "<|fim_middle|>" // Qwen
             || key == "<fim-middle>"
+            || key == "<fim_middle>" // StarCoder
             || key == "<｜fim▁end｜>" // DeepSeek
             || key == "<MID>"
             || key == "▁<MID>"
                 // CodeLlama
                 {
-                    config.fim_mid = *value;
+                    specials.fim_mid = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -340,7 +534,7 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_mid == NULL {
+            if specials.fim_mid == NULL {
                 if key== "<|fim_middle|>" // Qwen
             || key== "<fim-middle>"
             || key== "<｜fim▁end｜>"  // DeepSeek
@@ -348,7 +542,7 @@ impl Gpt2Tokenizer {
             || key== "▁<MID>"
                 // CodeLlama
                 {
-                    config.fim_mid = *value;
+                    specials.fim_mid = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -357,12 +551,13 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_pad == NULL {
+            if specials.fim_pad == NULL {
                 if key == "<|fim_pad|>" // Qwen
                 || key == "<fim-pad>"
+                || key == "<fim_pad>" // StarCoder
                 || key == "<PAD>"
                 {
-                    config.fim_pad = *value;
+                    specials.fim_pad = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -371,13 +566,14 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_rep == NULL {
+            if specials.fim_rep == NULL {
                 if key == "<|fim_repo|>"  // Qwen
             || key == "<|repo_name|>"
             || key == "<fim-repo>"
+            || key == "<repo_name>" // StarCoder
             || key == "<REPO>"
                 {
-                    config.fim_rep = *value;
+                    specials.fim_rep = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -386,11 +582,12 @@ impl Gpt2Tokenizer {
                     }
                 }
             }
-            if config.fim_sep == NULL {
-                if key == "<|file_sep|>"
-                // Qwen
+            if specials.fim_sep == NULL {
+                if key == "<|file_sep|>" // Qwen
+                || key == "<file_sep>"
+                // StarCoder
                 {
-                    config.fim_sep = *value;
+                    specials.fim_sep = *value;
                     if (id_to_token[*value as usize].attribute as i32
                         & TokenAttribute::Control as i32)
                         == 0
@@ -402,14 +599,14 @@ impl Gpt2Tokenizer {
         }
         let mut special_eog_ids = HashSet::new();
         // maintain a list of tokens that cause end-of-generation
-        if config.fim_pad != NULL && !special_eog_ids.contains(&config.fim_pad) {
-            special_eog_ids.insert(config.fim_pad);
+        if specials.fim_pad != NULL && !special_eog_ids.contains(&specials.fim_pad) {
+            special_eog_ids.insert(specials.fim_pad);
         }
-        if config.fim_rep != NULL && !special_eog_ids.contains(&config.fim_rep) {
-            special_eog_ids.insert(config.fim_rep);
+        if specials.fim_rep != NULL && !special_eog_ids.contains(&specials.fim_rep) {
+            special_eog_ids.insert(specials.fim_rep);
         }
-        if config.fim_sep != NULL && !special_eog_ids.contains(&config.fim_sep) {
-            special_eog_ids.insert(config.fim_sep);
+        if specials.fim_sep != NULL && !special_eog_ids.contains(&specials.fim_sep) {
+            special_eog_ids.insert(specials.fim_sep);
         }
 
         // 第二个循环也使用引用
@@ -439,7 +636,16 @@ impl Gpt2Tokenizer {
             }
         }
 
-        config.special_tokens = id_to_token
+        // eos、eot、eom 同样会结束生成
+        for id in [specials.eos, specials.eot, specials.eom] {
+            if (id as usize) < id_to_token.len() {
+                special_eog_ids.insert(id);
+            }
+        }
+        tokenizer.eog_tokens = special_eog_ids.into_iter().collect();
+        tokenizer.eog_tokens.sort_unstable();
+
+        tokenizer.special_tokens = id_to_token
             .iter()
             .enumerate() // 获取索引 (TokenId) 和 TokenData
             .filter(|(_, token_data)| {
@@ -448,15 +654,42 @@ impl Gpt2Tokenizer {
                     TokenAttribute::Control
                     | TokenAttribute::UserDefined
                     | TokenAttribute::Unknown => true,
+                    // 包括 Unused
                     _ => false,
                 }
             })
             .map(|(index, _)| index as TokenId) // 提取符合条件的 TokenId (索引)
             .collect(); // 收集到 Vec<TokenId> 中
-        config.token_to_id = token_to_id;
-        config.id_to_token = id_to_token;
-        config.bpe_ranks = bpe_ranks;
-        config
+        tokenizer.token_to_id = token_to_id;
+        tokenizer.id_to_token = id_to_token;
+        tokenizer.bpe_ranks = bpe_ranks;
+        Ok(tokenizer)
+    }
+    /// 替换预分词规则
+    pub fn set_pretokenize(&mut self, kind: PretokenizeKind) {
+        let split_on_graphemes = self.session.get_mut().split_on_graphemes();
+        let cache_capacity = self.session.get_mut().cache_capacity();
+        let rank_cache = self.session.get_mut().rank_cache_enabled();
+        self.session = LlmTokenizerBpeSession::new(LlmTokenizerBpe::new(kind)).into();
+        self.set_split_on_graphemes(split_on_graphemes);
+        self.set_cache_capacity(cache_capacity);
+        self.set_rank_cache(rank_cache)
+    }
+    /// 设置预分词片段编码结果的 LRU 缓存容量，0 表示关闭（默认）
+    ///
+    /// 命中缓存的片段跳过合并过程，适合重复内容较多的输入。
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.session.get_mut().set_cache_capacity(capacity)
+    }
+    /// 开启或关闭 BPE 会话中二元组合并排名的缓存，默认关闭
+    ///
+    /// 缓存在多次分词之间保留，重复出现的二元组不再查找完整的合词表。
+    pub fn set_rank_cache(&mut self, enabled: bool) {
+        self.session.get_mut().set_rank_cache_enabled(enabled)
+    }
+    /// 设置预分词时是否保持扩展字素簇完整
+    pub fn set_split_on_graphemes(&mut self, val: bool) {
+        self.session.get_mut().set_split_on_graphemes(val)
     }
     /// 将文本字符串转换为标记 ID
     ///
@@ -470,21 +703,85 @@ impl Gpt2Tokenizer {
             NULL
         }
     }
+    /// 将标记序列解码为文本，还原字节编码（SPM 还原空格替换字符），
+    /// 并在设置了 `clean_spaces` 时清理多余空格
+    pub fn decode_sequence(&self, tokens: &[TokenId]) -> String {
+        self.detokenize(tokens, false, self.config.flags.clean_spaces)
+    }
+    /// [`tokenize`](Self::tokenize) 的逆过程：还原字节映射和空格转义，拼接为文本。
+    ///
+    /// `skip_special` 时跳过控制标记和未知标记，`clean` 时删除标点前多余的空格。
+    pub fn detokenize(&self, tokens: &[TokenId], skip_special: bool, clean: bool) -> String {
+        let mut bytes = Vec::new();
+        for &token in tokens {
+            if skip_special
+                && self.try_get_token_data(token).is_some_and(|data| {
+                    matches!(
+                        data.attribute,
+                        TokenAttribute::Control | TokenAttribute::Unknown
+                    )
+                })
+            {
+                continue;
+            }
+            self.decode_piece_into(token, &mut bytes)
+        }
+        let text = String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+        if clean {
+            llama_clean_spaces(&text)
+        } else {
+            text
+        }
+    }
+    /// 将单个标记还原为原始字节追加到 `bytes`，超出词表范围的标记按 unk 处理
+    fn decode_piece_into(&self, token: TokenId, bytes: &mut Vec<u8>) {
+        let piece = self
+            .try_get_token_data(token)
+            .or_else(|| self.try_get_token_data(self.config.specials.unk))
+            .map_or("", |data| data.text.as_str());
+        bytes.extend_from_slice(&self.pre_decode(piece.as_bytes()))
+    }
+    /// 判断标记是否会结束生成
+    pub fn is_eog(&self, token: TokenId) -> bool {
+        self.eog_tokens.binary_search(&token).is_ok()
+    }
+    /// 换行符对应的标记，词表中没有换行符时为 pad，没有 pad 时为 `u32::MAX`
+    pub fn linefeed_token(&self) -> TokenId {
+        self.config.specials.linefeed
+    }
+    /// 所有结束生成的标记
+    pub fn eog_tokens(&self) -> &[TokenId] {
+        &self.eog_tokens
+    }
     pub fn n_tokens(&self) -> u32 {
         self.id_to_token.len() as u32
     }
     /// 添加 BOS 标记
     pub fn append_bos(&self, output: &mut Vec<TokenId>) -> bool {
-        if self.add_bos {
-            output.push(self.bos);
+        if self.config.flags.add_bos {
+            output.push(self.config.specials.bos);
             return true;
         }
         false
     }
+    /// 自动添加的 BOS 后是否紧跟着文本中的 BOS
+    fn duplicate_bos(&self, output: &[TokenId]) -> bool {
+        let bos = self.config.specials.bos;
+        self.config.flags.add_bos && output.starts_with(&[bos, bos])
+    }
+    /// 自动添加的 BOS 后紧跟着文本中的 BOS 时给出警告
+    fn warn_duplicate_bos(&self, output: &[TokenId]) {
+        if self.duplicate_bos(output) {
+            log::warn!(
+                "Added a BOS token to the prompt as specified by the model but the prompt also starts with a BOS token"
+            );
+        }
+    }
     /// 添加 EOS 标记
     pub fn append_eos(&self, output: &mut Vec<TokenId>) -> bool {
-        if self.add_eos {
-            output.push(self.eos);
+        if self.config.flags.add_eos {
+            output.push(self.config.specials.eos);
             return true;
         }
         false
@@ -492,48 +789,139 @@ impl Gpt2Tokenizer {
     pub fn get_token_data(&self, id: TokenId) -> &TokenData {
         &self.id_to_token[id as usize]
     }
+    /// 获取标记数据，标记超出词表范围时返回 `None`
+    pub fn try_get_token_data(&self, id: TokenId) -> Option<&TokenData> {
+        self.id_to_token.get(id as usize)
+    }
     /// 将单个字节转换为标记 ID
+    ///
+    /// # Panics
+    ///
+    /// 词表中没有这个字节对应的标记，或词表类型不支持按字节查找时 panic。
     pub fn byte_to_token(&self, ch: u8) -> TokenId {
-        // 十六进制字符数组
-        static HEX: &[u8; 16] = b"0123456789ABCDEF";
-
+        self.try_byte_to_token(ch).expect("无法找到字节对应的标记")
+    }
+    /// 将单个字节转换为标记 ID，找不到对应的标记时返回 `None`
+    pub fn try_byte_to_token(&self, ch: u8) -> Option<TokenId> {
         match self.vocab_type {
             VocabType::Spm | VocabType::Ugm => {
-                // 创建格式为 "<0xXY>" 的字符串，其中 XY 是字节的十六进制表示
-                let buf = format!(
-                    "<0x{}{}>",
-                    HEX[(ch >> 4) as usize] as char,
-                    HEX[(ch & 15) as usize] as char
-                );
-
-                // 尝试在词汇表中查找该字符串
-                if let Some(token) = self.token_to_id.get(&buf) {
-                    return *token;
-                }
-
-                // 如果找不到，尝试回退到仅将字节作为字符串
-                let buf2 = String::from_utf8_lossy(&[ch]).to_string();
-
-                // 使用 at 方法获取标记 ID，如果不存在则会 panic
-                *self.token_to_id.get(&buf2).expect("无法找到字节对应的标记")
+                // 先查找格式为 "<0xXY>" 的字节词，找不到时回退到字节本身
+                self.token_to_id
+                    .get(&format!("<0x{ch:02X}>"))
+                    .or_else(|| self.token_to_id.get(&*String::from_utf8_lossy(&[ch])))
+                    .copied()
             }
-
+            // 对于 WPM 和 BPE 类型，使用 unicode_byte_to_utf8 函数
             VocabType::Wpm | VocabType::Bpe => {
-                // 对于 WPM 和 BPE 类型，使用 unicode_byte_to_utf8 函数
-                let utf8_str = unicode_byte_to_utf8(ch);
-
-                // 使用 at 方法获取标记 ID，如果不存在则会 panic
-                *self
-                    .token_to_id
-                    .get(&utf8_str)
-                    .expect("无法找到字节对应的标记")
+                self.token_to_id.get(&unicode_byte_to_utf8(ch)).copied()
             }
-
-            _ => {
-                // 对于其他类型，终止程序
-                panic!("致命错误：不支持的词汇表类型")
+            _ => None,
+        }
+    }
+    /// 设置了 `add_space_prefix` 时为 BPE 分词在文本开头添加空格，
+    /// 只作用于输入开头的片段，已经以空白开头的文本不再添加。
+    fn bpe_space_prefix<'t>(&self, text: &'t str, at_start: bool) -> Cow<'t, str> {
+        if self.config.flags.add_space_prefix
+            && at_start
+            && !text.is_empty()
+            && !text.starts_with(char::is_whitespace)
+        {
+            Cow::Owned(format!(" {text}"))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+    /// 按开关在分词前规范化输入：去掉开头的 BOM、去掉零宽字符、统一换行符、全角转半角。
+    fn normalize_input<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let flags = &self.config.flags;
+        let mut text = Cow::Borrowed(text);
+        if flags.strip_bom
+            && let Some(rest) = text.strip_prefix('\u{FEFF}')
+        {
+            text = Cow::Owned(rest.to_string())
+        }
+        if flags.strip_zero_width && text.contains(ZERO_WIDTH) {
+            text = Cow::Owned(text.replace(ZERO_WIDTH, ""))
+        }
+        if flags.normalize_newlines && text.contains("\r\n") {
+            text = Cow::Owned(text.replace("\r\n", "\n"))
+        }
+        if flags.fullwidth_to_halfwidth && text.contains(|c| halfwidth(c) != c) {
+            text = Cow::Owned(text.chars().map(halfwidth).collect())
+        }
+        text
+    }
+    /// 与 [`normalize_input`](Self::normalize_input) 相同，同时给出规范化结果的每个字节在原文中的位置。
+    ///
+    /// 位置是产生这个字节的字符在原文中的起点，末尾额外保存原文的长度。
+    fn normalize_with_offsets(&self, text: &str) -> (String, Vec<usize>) {
+        let flags = &self.config.flags;
+        let mut ans = String::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        let mut push = |c: char, pos: usize| {
+            ans.push(c);
+            offsets.resize(ans.len(), pos)
+        };
+        let mut chars = text.char_indices().peekable();
+        if flags.strip_bom {
+            chars.next_if(|&(_, c)| c == '\u{FEFF}');
+        }
+        // 等待下一个字符确定 "\r" 是否属于 "\r\n"
+        let mut cr = None;
+        for (i, c) in chars {
+            if flags.strip_zero_width && ZERO_WIDTH.contains(&c) {
+                continue;
             }
+            let pos = match cr.take() {
+                Some(pos) if c == '\n' => pos,
+                Some(pos) => {
+                    push('\r', pos);
+                    i
+                }
+                None => i,
+            };
+            if flags.normalize_newlines && c == '\r' {
+                cr = Some(i);
+                continue;
+            }
+            push(
+                if flags.fullwidth_to_halfwidth {
+                    halfwidth(c)
+                } else {
+                    c
+                },
+                pos,
+            )
+        }
+        if let Some(pos) = cr {
+            push('\r', pos)
+        }
+        offsets.push(text.len());
+        (ans, offsets)
+    }
+    /// 为 SPM 分词准备文本：按需添加空格，并将空格转义为 U+2581。
+    ///
+    /// 设置了 `treat_whitespace_as_suffix` 时，添加的空格放在文本之后而不是之前。
+    /// 文本在这一侧已经有空格时不再添加，避免产生多余的空格词。
+    fn spm_escape(&self, text: &str, is_prev_special: bool) -> String {
+        let mut ans = String::with_capacity(text.len() + 1);
+        let has_space = if self.config.flags.treat_whitespace_as_suffix {
+            text.ends_with(' ')
+        } else {
+            text.starts_with(' ')
+        };
+        let add_space = self.config.flags.add_space_prefix && is_prev_special && !has_space;
+        if add_space && !self.config.flags.treat_whitespace_as_suffix {
+            ans.push(' ')
+        }
+        ans.push_str(text);
+        if add_space && self.config.flags.treat_whitespace_as_suffix {
+            ans.push(' ')
         }
+        if self.config.flags.escape_whitespaces {
+            llama_escape_whitespace(&mut ans, self.meta_space)
+        }
+        ans
     }
     pub fn find_bpe_rank(&self, token_left: &str, token_right: &str) -> i32 {
         match self
@@ -544,59 +932,80 @@ impl Gpt2Tokenizer {
             None => -1,
         }
     }
+    /// 分词，`add_special` 决定是否添加 bos/eos，`parse_special` 决定是否识别文本中的控制标记。
+    ///
+    /// 空文本只输出 bos/eos。只含空白的文本按预分词规则切分，不会产生空片段：
+    /// GPT-2 等规则将连续的空白作为一个片段合并，deepseek 等规则将换行单独切分。
     pub fn tokenize<'a>(
         &self,
         raw_text: &'a str,
         add_special: bool,
         parse_special: bool,
     ) -> Vec<u32> {
+        self.tokenize_with_mode(raw_text, add_special, parse_special.into())
+    }
+    /// 与 [`tokenize`](Self::tokenize) 相同，由 `mode` 细分识别哪些特殊标记。
+    pub fn tokenize_with_mode(
+        &self,
+        raw_text: &str,
+        add_special: bool,
+        mode: SpecialParseMode,
+    ) -> Vec<u32> {
+        let normalized = self.normalize_input(raw_text);
+        let raw_text = &*normalized;
         let mut buffer = LinkedList::new();
         let mut output = Vec::new();
+        // 文本中不可能出现特殊标记时，跳过切分直接交给 BPE 会话
+        if self.vocab_type == VocabType::Bpe && !self.needs_partition(mode) {
+            if add_special {
+                self.append_bos(&mut output);
+            }
+            if !raw_text.is_empty() {
+                self.session.borrow_mut().tokenize(
+                    &self.bpe_space_prefix(raw_text, true),
+                    &mut output,
+                    self,
+                );
+            }
+            if add_special {
+                self.warn_duplicate_bos(&output);
+                self.append_eos(&mut output);
+            }
+            return output;
+        }
         if !raw_text.is_empty() {
             buffer.push_front(
                 FragmentBufferVariant::new_raw_text(raw_text.to_string(), 0, raw_text.len() as i64)
                     .unwrap(),
             );
-            self.tokenizer_st_partition(&mut buffer, parse_special);
+            self.tokenizer_st_partition(&mut buffer, mode);
         }
         match self.vocab_type {
-            VocabType::None => todo!(),
+            VocabType::None => {
+                log::warn!("tokenize called without a vocab type, nothing is produced")
+            }
             VocabType::Spm => {
                 let mut is_prev_special = true; // prefix with space if first token
-                if add_special && self.add_bos {
-                    output.push(self.bos);
+                if add_special && self.config.flags.add_bos {
+                    output.push(self.config.specials.bos);
                     is_prev_special = true;
                 }
+                let mut session = LlmTokenizerSpmSession::new();
                 for fragment in buffer.iter_mut() {
                     let substring = &fragment.raw_text
                         [(fragment.offset as usize)..(fragment.offset + fragment.length) as usize];
-                    let mut text = String::new();
                     if fragment.variant_type == FragmentBufferVariantType::RawText {
-                        if self.add_space_prefix && is_prev_special {
-                            text.push(' ');
-                        }
-                        text.push_str(substring);
-
-                        llama_escape_whitespace(&mut text);
-                        todo!();
-                        // SPM_SESSION.get_mut().unwrap()
-                        //     .tokenize(&text, &mut output);
+                        let text = self.spm_escape(substring, is_prev_special);
+                        session.tokenize(&text, &mut output, self);
                         is_prev_special = false;
                     } else {
                         output.push(fragment.token);
                         is_prev_special = true;
                     }
-                    // 检查是否有重复的 BOS 标记
-                    if add_special && self.add_bos && output.len() >= 2 && output[1] == self.bos {
-                        log::warn!(
-                            " Added a BOS token to the prompt as specified by the model but the prompt"
-                        );
-                    }
-
-                    // 添加 EOS 标记
-                    if add_special && self.add_eos {
-                        output.push(self.eos);
-                    }
+                }
+                if add_special {
+                    self.warn_duplicate_bos(&output);
+                    self.append_eos(&mut output);
                 }
             }
             VocabType::Bpe => {
@@ -606,18 +1015,17 @@ impl Gpt2Tokenizer {
                 }
                 for fragment in buffer.iter_mut() {
                     if fragment.variant_type == FragmentBufferVariantType::RawText {
-                        let substring: String = fragment
-                            .raw_text
-                            .chars()
-                            .skip(fragment.offset as usize)
-                            .take(fragment.length as usize)
-                            .collect();
-                        session_ref.tokenize(substring.as_str(), &mut output, &self);
+                        let substring = &fragment.raw_text[(fragment.offset as usize)
+                            ..(fragment.offset + fragment.length) as usize];
+                        let substring = self.bpe_space_prefix(substring, fragment.offset == 0);
+                        session_ref.tokenize(&substring, &mut output, &self);
                     } else {
+                        output.push(fragment.token)
                     }
                 }
 
                 if add_special {
+                    self.warn_duplicate_bos(&output);
                     self.append_eos(&mut output);
                 }
             }
@@ -627,143 +1035,213 @@ impl Gpt2Tokenizer {
         }
         output
     }
+    /// 按模板编码，特殊标记直接输出，不经过文本查找，也不添加 bos/eos
+    ///
+    /// 适合对话模板这类特殊标记与文本紧密相连的场景，用户文本中的特殊标记文本不会被误识别。
+    pub fn encode_template(&self, segments: &[Segment]) -> Vec<TokenId> {
+        let mut ans = Vec::new();
+        for segment in segments {
+            match *segment {
+                Segment::Text(text) => ans.extend(self.tokenize(text, false, false)),
+                Segment::Special(token) => ans.push(token),
+            }
+        }
+        ans
+    }
+    /// 识别文本中的特殊标记，返回切分后尚未分词的片段。
+    ///
+    /// 与 [`Method::encode`] 一样先规范化文本，再按 `parse_special` 决定是否识别控制标记，
+    /// 可用于检查特殊标记在文本中的位置。片段的范围映射回原文，规范化去掉的字符归入前一个片段。
+    pub fn segment(&self, text: &str) -> Vec<Fragment> {
+        let (normalized, offsets) = self.normalize_with_offsets(text);
+        if normalized.is_empty() {
+            return vec![];
+        }
+        let mut buffer = LinkedList::new();
+        buffer.push_front(
+            FragmentBufferVariant::new_raw_text(normalized.clone(), 0, normalized.len() as i64)
+                .unwrap(),
+        );
+        self.tokenizer_st_partition(&mut buffer, self.config.flags.parse_special.into());
+        buffer
+            .into_iter()
+            .map(|fragment| match fragment.variant_type {
+                FragmentBufferVariantType::RawText => Fragment::Text(
+                    offsets[fragment.offset as usize]
+                        ..offsets[(fragment.offset + fragment.length) as usize],
+                ),
+                FragmentBufferVariantType::Token => Fragment::Special(fragment.token),
+            })
+            .collect()
+    }
+    /// 是否有特殊标记需要在文本中查找
+    ///
+    /// 不解析特殊标记时，控制标记和未知标记不参与切分。
+    fn needs_partition(&self, mode: SpecialParseMode) -> bool {
+        self.special_tokens
+            .iter()
+            .any(|&id| mode.parses(self.id_to_token[id as usize].attribute))
+    }
+    /// 在文本中查找特殊标记第一次出现的字节位置
+    ///
+    /// 设置了 `special_case_insensitive` 时忽略 ascii 字母的大小写，匹配的长度与特殊标记相同。
+    /// 空的特殊标记不匹配任何位置。
+    fn find_special(&self, haystack: &str, special: &str) -> Option<usize> {
+        if special.is_empty() {
+            return None;
+        }
+        if !self.config.flags.special_case_insensitive {
+            return haystack.find(special);
+        }
+        haystack
+            .as_bytes()
+            .windows(special.len())
+            .position(|w| w.eq_ignore_ascii_case(special.as_bytes()))
+    }
+    /// 查找特殊标记第一个有效的匹配，返回匹配部分的字节范围。
+    ///
+    /// `single_word` 要求匹配的两侧不是单词字符（字母、数字或下划线）。
+    fn match_special(
+        &self,
+        haystack: &str,
+        pattern: &str,
+        single_word: bool,
+    ) -> Option<Range<usize>> {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let mut start = 0;
+        loop {
+            let pos = start + self.find_special(&haystack[start..], pattern)?;
+            let end = pos + pattern.len();
+            if !single_word
+                || !is_word(haystack[..pos].chars().next_back())
+                    && !is_word(haystack[end..].chars().next())
+            {
+                return Some(pos..end);
+            }
+            start = pos + haystack[pos..].chars().next().map_or(1, char::len_utf8)
+        }
+    }
     /// 检查文本是否有特殊标记，如果有则将其分割
     ///
     /// 例如，将 "Hello <|eot_id|> World" 分割为 "Hello" 和 "World"
     fn tokenizer_st_partition(
         &self,
         buffer: &mut LinkedList<FragmentBufferVariant>,
-        parse_special: bool,
+        mode: SpecialParseMode,
     ) {
         // 遍历每个特殊标记
         for special_id in &self.special_tokens {
             let data = self.id_to_token[*special_id as usize].clone();
-            let text = &data.text;
+            // 规范化的标记以转义后的形式保存，与还原后的原文比较
+            let text = match data.attribute {
+                TokenAttribute::Normalized => match self.pre_decode(data.text.as_bytes()) {
+                    Cow::Owned(bytes) => String::from_utf8(bytes).unwrap_or(data.text.clone()),
+                    Cow::Borrowed(_) => data.text.clone(),
+                },
+                _ => data.text.clone(),
+            };
+            let single_word = matches!(data.attribute, TokenAttribute::SingleWord);
 
-            // 如果不解析特殊标记且当前标记是控制标记或未知标记，则跳过
-            if !parse_special
-                && ((data.attribute as u32)
-                    & (TokenAttribute::Control as u32 | TokenAttribute::Unknown as u32))
-                    != 0
-            {
+            // 跳过当前模式不识别的特殊标记
+            if !mode.parses(data.attribute) {
                 continue;
             }
 
-            // 遍历每个文本片段
+            // 遍历每个文本片段，包含特殊标记的原始文本片段被替换为切分后的片段
             let mut cursor = buffer.cursor_front_mut();
             while let Some(fragment) = cursor.current() {
-                // 如果片段是原始文本（尚未处理）
-                if fragment.variant_type == FragmentBufferVariantType::RawText {
-                    let FragmentBufferVariant {
-                        raw_text,
-                        offset,
-                        length,
-                        ..
-                    } = &fragment.clone();
-                    let mut raw_text_base_offset = *offset;
-                    let mut raw_text_base_length = *length;
-
-                    // 在文本中循环查找特殊标记
-                    loop {
-                        // 在当前片段中查找特殊标记的第一次出现
-                        let text_slice = &raw_text[raw_text_base_offset as usize
-                            ..(raw_text_base_offset + raw_text_base_length) as usize];
-                        let match_pos = text_slice.find(text);
-
-                        // 如果没有找到，停止处理该片段
-                        let match_pos = match match_pos {
-                            None => break,
-                            Some(pos) => raw_text_base_offset as usize + pos,
-                        };
-
-                        // 如果匹配位置在基础偏移量之后，处理左侧文本
-                        if match_pos > raw_text_base_offset as usize {
-                            let left_reminder_offset = raw_text_base_offset as i64;
-                            let mut left_reminder_length =
-                                match_pos as i64 - raw_text_base_offset as i64;
-
-                            // 如果需要去除左侧空白
-                            if (data.attribute as u32 & TokenAttribute::LStrIp as u32) != 0 {
-                                while left_reminder_length > 0 {
-                                    let last_char = raw_text
-                                        .chars()
-                                        .nth(
-                                            (left_reminder_offset + left_reminder_length - 1)
-                                                as usize,
-                                        )
-                                        .unwrap();
-                                    if !last_char.is_whitespace() {
-                                        break;
-                                    }
-                                    left_reminder_length -= 1;
-                                }
-                            }
-
-                            // 插入左侧文本片段
-                            if left_reminder_length > 0 {
-                                cursor.insert_after(
-                                    FragmentBufferVariant::new_raw_text(
-                                        raw_text.clone(),
-                                        left_reminder_offset,
-                                        left_reminder_length,
-                                    )
-                                    .unwrap(),
-                                );
-                                cursor.move_next();
-                            }
-                        }
+                if fragment.variant_type != FragmentBufferVariantType::RawText
+                    || self
+                        .match_special(fragment.text(), &text, single_word)
+                        .is_none()
+                {
+                    cursor.move_next();
+                    continue;
+                }
+                let FragmentBufferVariant {
+                    raw_text,
+                    offset,
+                    length,
+                    ..
+                } = cursor.remove_current().unwrap();
+                let mut raw_text_base_offset = offset;
+                let mut raw_text_base_length = length;
 
-                        // 插入特殊标记
-                        cursor.insert_after(FragmentBufferVariant::new_token(*special_id));
-                        cursor.move_next();
-
-                        // 处理右侧文本
-                        let right_start = match_pos + text.len();
-                        if right_start < (raw_text_base_offset + raw_text_base_length) as usize {
-                            let mut right_reminder_offset = right_start as i64;
-                            let mut right_reminder_length = raw_text_base_length
-                                - ((match_pos as u64 - raw_text_base_offset as u64)
-                                    + text.len() as u64);
-
-                            // 如果需要去除右侧空白
-                            if (data.attribute as u32 & TokenAttribute::RStrIp as u32) != 0 {
-                                while right_reminder_length > 0 {
-                                    let next_char = raw_text
-                                        .chars()
-                                        .nth(right_reminder_offset as usize)
-                                        .unwrap();
-                                    if !next_char.is_whitespace() {
-                                        break;
-                                    }
-                                    right_reminder_offset += 1;
-                                    right_reminder_length -= 1;
-                                }
-                            }
-
-                            // 插入右侧文本片段
-                            if right_reminder_length > 0 {
-                                cursor.insert_after(
-                                    FragmentBufferVariant::new_raw_text(
-                                        raw_text.clone(),
-                                        right_reminder_offset,
-                                        right_reminder_length as i64,
-                                    )
-                                    .unwrap(),
-                                );
-                                cursor.move_next();
-                            }
-
-                            // 继续处理右侧文本
-                            raw_text_base_offset = right_reminder_offset as u64;
-                            raw_text_base_length = right_reminder_length;
-                        } else {
-                            // 删除当前片段并退出循环
-                            cursor.remove_current();
+                // 在文本中循环查找特殊标记，切分出的片段依次插入到被删除的片段处
+                loop {
+                    // 在当前片段中查找特殊标记的第一次出现
+                    let text_slice = &raw_text[raw_text_base_offset as usize
+                        ..(raw_text_base_offset + raw_text_base_length) as usize];
+                    let match_pos = match self.match_special(text_slice, &text, single_word) {
+                        Some(range) => raw_text_base_offset as usize + range.start,
+                        // 没有更多的特殊标记，剩余的文本作为一个片段
+                        None => {
+                            cursor.insert_before(
+                                FragmentBufferVariant::new_raw_text(
+                                    raw_text.clone(),
+                                    raw_text_base_offset as i64,
+                                    raw_text_base_length as i64,
+                                )
+                                .unwrap(),
+                            );
                             break;
                         }
+                    };
+
+                    // 如果匹配位置在基础偏移量之后，处理左侧文本
+                    if match_pos > raw_text_base_offset as usize {
+                        let left_reminder_offset = raw_text_base_offset as i64;
+                        let mut left_reminder_length =
+                            match_pos as i64 - raw_text_base_offset as i64;
+
+                        // 如果需要去除左侧空白，偏移和长度都以字节为单位
+                        if (data.attribute as u32 & TokenAttribute::LStrIp as u32) != 0 {
+                            let left = &raw_text[left_reminder_offset as usize..match_pos];
+                            left_reminder_length =
+                                left.trim_end_matches(char::is_whitespace).len() as i64;
+                        }
+
+                        // 插入左侧文本片段
+                        if left_reminder_length > 0 {
+                            cursor.insert_before(
+                                FragmentBufferVariant::new_raw_text(
+                                    raw_text.clone(),
+                                    left_reminder_offset,
+                                    left_reminder_length,
+                                )
+                                .unwrap(),
+                            );
+                        }
+                    }
+
+                    // 插入特殊标记
+                    cursor.insert_before(FragmentBufferVariant::new_token(*special_id));
+
+                    // 处理右侧文本
+                    let right_start = match_pos + text.len();
+                    if right_start >= (raw_text_base_offset + raw_text_base_length) as usize {
+                        break;
+                    }
+                    let mut right_reminder_offset = right_start as i64;
+                    let mut right_reminder_length = raw_text_base_length
+                        - ((match_pos as u64 - raw_text_base_offset) + text.len() as u64);
+
+                    // 如果需要去除右侧空白
+                    if (data.attribute as u32 & TokenAttribute::RStrIp as u32) != 0 {
+                        let right = &raw_text[right_reminder_offset as usize..]
+                            [..right_reminder_length as usize];
+                        let trimmed = right.trim_start_matches(char::is_whitespace);
+                        right_reminder_offset += (right.len() - trimmed.len()) as i64;
+                        right_reminder_length = trimmed.len() as _;
+                    }
+                    if right_reminder_length == 0 {
+                        break;
                     }
+
+                    // 继续处理右侧文本
+                    raw_text_base_offset = right_reminder_offset as u64;
+                    raw_text_base_length = right_reminder_length;
                 }
-                cursor.move_next();
             }
         }
     }
@@ -773,30 +1251,30 @@ impl std::fmt::Debug for Gpt2Tokenizer {
         f.debug_struct("Gpt2Tokenizer")
             // 这里只添加您想要显示的字段
             .field("vocab_type", &self.vocab_type)
-            .field("bos", &self.bos)
-            .field("eos", &self.eos)
-            .field("eot", &self.eot)
-            .field("eom", &self.eom)
-            .field("unk", &self.unk)
-            .field("pad", &self.pad)
-            .field("linefeed", &self.linefeed)
-            .field("fim_pre", &self.fim_pre)
-            .field("fim_suf", &self.fim_suf)
-            .field("fim_mid", &self.fim_mid)
-            .field("fim_pad", &self.fim_pad)
-            .field("fim_rep", &self.fim_rep)
-            .field("fim_sep", &self.fim_sep)
-            .field("add_bos", &self.add_bos)
-            .field("add_eos", &self.add_eos)
-            .field("add_space_prefix", &self.add_space_prefix)
+            .field("bos", &self.config.specials.bos)
+            .field("eos", &self.config.specials.eos)
+            .field("eot", &self.config.specials.eot)
+            .field("eom", &self.config.specials.eom)
+            .field("unk", &self.config.specials.unk)
+            .field("pad", &self.config.specials.pad)
+            .field("linefeed", &self.config.specials.linefeed)
+            .field("fim_pre", &self.config.specials.fim_pre)
+            .field("fim_suf", &self.config.specials.fim_suf)
+            .field("fim_mid", &self.config.specials.fim_mid)
+            .field("fim_pad", &self.config.specials.fim_pad)
+            .field("fim_rep", &self.config.specials.fim_rep)
+            .field("fim_sep", &self.config.specials.fim_sep)
+            .field("add_bos", &self.config.flags.add_bos)
+            .field("add_eos", &self.config.flags.add_eos)
+            .field("add_space_prefix", &self.config.flags.add_space_prefix)
             // 不添加您不想显示的字段：token_to_id, special_tokens, id_to_token, bpe_ranks
             .finish()
     }
 }
 impl Method for Gpt2Tokenizer {
-    /// gpt2 没有unk 这里暂时返回0
+    /// 没有 unk 时返回 [`NULL`]
     fn unk_token(&self) -> crate::utok {
-        0
+        self.config.specials.unk
     }
 
     fn vocab_size(&self) -> usize {
@@ -810,13 +1288,66 @@ impl Method for Gpt2Tokenizer {
     }
 
     fn encode(&self, text: &str) -> impl IntoIterator<Item = crate::utok> + '_ {
-        self.tokenize(text, true, true)
+        self.tokenize(text, true, self.config.flags.parse_special)
             .into_iter()
             .map(|token_id| token_id)
     }
 
+    /// 超出词表范围的标记解码为 unk，没有 unk 时解码为空
     fn decode(&self, token: crate::utok) -> &[u8] {
-        self.get_token_data(token).text.as_bytes()
+        self.try_get_token_data(token)
+            .or_else(|| self.try_get_token_data(self.config.specials.unk))
+            .map_or(&[], |data| data.text.as_bytes())
+    }
+
+    fn token_of(&self, piece: &str) -> Option<crate::utok> {
+        self.token_to_id.get(piece).copied()
+    }
+
+    /// 还原字节编码，SPM 还原空格替换字符，不是合法 utf-8 的输入原样返回
+    fn pre_decode<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            return Cow::Borrowed(bytes);
+        };
+        match self.vocab_type {
+            VocabType::Spm => {
+                let mut text = text.to_string();
+                llama_unescape_whitespace(&mut text, self.meta_space);
+                Cow::Owned(text.into_bytes())
+            }
+            _ => Cow::Owned(unicode_byte_decoding(text)),
+        }
+    }
+
+    fn encode_with_special(&self, text: &str, add_bos: bool, add_eos: bool) -> Vec<crate::utok> {
+        let mut ans = Vec::new();
+        if add_bos && self.config.specials.bos != NULL {
+            ans.push(self.config.specials.bos)
+        }
+        ans.extend(self.tokenize(text, false, self.config.flags.parse_special));
+        if add_eos && self.config.specials.eos != NULL {
+            ans.push(self.config.specials.eos)
+        }
+        ans
+    }
+
+    /// 还原字节编码（SPM 还原空格替换字符）后显示
+    fn decode_each(&self, tokens: &[crate::utok]) -> Vec<String> {
+        let mut bytes = Vec::new();
+        tokens
+            .iter()
+            .map(|&t| {
+                bytes.clear();
+                self.decode_piece_into(t, &mut bytes);
+                String::from_utf8_lossy(&bytes).into_owned()
+            })
+            .collect()
+    }
+
+    fn iter_vocab(&self) -> impl Iterator<Item = (crate::utok, Cow<'_, [u8]>)> + '_ {
+        (0..)
+            .zip(&self.id_to_token)
+            .map(|(id, data)| (id, Cow::Borrowed(data.text.as_bytes())))
     }
 }
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -834,6 +1365,11 @@ struct FragmentBufferVariant {
     length: u64,
 }
 impl FragmentBufferVariant {
+    /// 原始文本片段的内容，标记片段为空
+    fn text(&self) -> &str {
+        &self.raw_text[self.offset as usize..(self.offset + self.length) as usize]
+    }
+
     // 创建 Token 类型的变体
     fn new_token(token: u32) -> Self {
         Self {
@@ -857,6 +1393,12 @@ impl FragmentBufferVariant {
         if (offset + length) as usize > text.len() {
             return Err("offset + length exceeds text length");
         }
+        // 偏移量以字节为单位，切片时不能拆开多字节字符
+        if !text.is_char_boundary(offset as usize)
+            || !text.is_char_boundary((offset + length) as usize)
+        {
+            return Err("offset and length must lie on utf-8 char boundaries");
+        }
 
         Ok(Self {
             variant_type: FragmentBufferVariantType::RawText,
@@ -867,3 +1409,1252 @@ impl FragmentBufferVariant {
         })
     }
 }
+
+#[cfg(test)]
+mod gpt2_tests {
+    use super::*;
+    use ggus::{GGufMetaDataValueType as Ty, GGufMetaMap};
+
+    /// 以内存中的键值对模拟 gguf 元信息
+    #[derive(Default)]
+    pub(super) struct MockGGuf(HashMap<String, (Ty, Vec<u8>)>);
+
+    impl GGufMetaMap for MockGGuf {
+        fn get(&self, key: &str) -> Option<(Ty, &[u8])> {
+            self.0.get(key).map(|(ty, val)| (*ty, &**val))
+        }
+    }
+
+    impl MockGGuf {
+        pub fn with_vocab(tokens: &[&str], token_type: &[i32], merges: &[&str]) -> Self {
+            Self::default()
+                .str_arr("tokenizer.ggml.tokens", tokens)
+                .i32_arr("tokenizer.ggml.token_type", token_type)
+                .str_arr("tokenizer.ggml.merges", merges)
+                .bool("tokenizer.ggml.add_bos_token", false)
+        }
+
+        pub fn str(mut self, key: &str, val: &str) -> Self {
+            let mut buf = Vec::new();
+            put_str(&mut buf, val);
+            self.0.insert(key.into(), (Ty::String, buf));
+            self
+        }
+
+        pub fn u32(mut self, key: &str, val: u32) -> Self {
+            self.0
+                .insert(key.into(), (Ty::U32, val.to_le_bytes().into()));
+            self
+        }
+
+        pub fn bool(mut self, key: &str, val: bool) -> Self {
+            self.0.insert(key.into(), (Ty::Bool, vec![val as u8]));
+            self
+        }
+
+        pub fn str_arr(mut self, key: &str, val: &[&str]) -> Self {
+            let mut buf = arr_header(Ty::String, val.len());
+            for s in val {
+                put_str(&mut buf, s)
+            }
+            self.0.insert(key.into(), (Ty::Array, buf));
+            self
+        }
+
+        pub fn remove(mut self, key: &str) -> Self {
+            self.0.remove(key);
+            self
+        }
+
+        pub fn i32_arr(mut self, key: &str, val: &[i32]) -> Self {
+            let mut buf = arr_header(Ty::I32, val.len());
+            for x in val {
+                buf.extend_from_slice(&x.to_le_bytes())
+            }
+            self.0.insert(key.into(), (Ty::Array, buf));
+            self
+        }
+
+        pub fn f32_arr(mut self, key: &str, val: &[f32]) -> Self {
+            let mut buf = arr_header(Ty::F32, val.len());
+            for x in val {
+                buf.extend_from_slice(&x.to_le_bytes())
+            }
+            self.0.insert(key.into(), (Ty::Array, buf));
+            self
+        }
+    }
+
+    fn put_str(buf: &mut Vec<u8>, s: &str) {
+        buf.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        buf.extend_from_slice(s.as_bytes())
+    }
+
+    fn arr_header(ty: Ty, len: usize) -> Vec<u8> {
+        let mut buf = (ty as u32).to_le_bytes().to_vec();
+        buf.extend_from_slice(&(len as u64).to_le_bytes());
+        buf
+    }
+
+    /// 手动构造一个 SPM 词表的分词器
+    pub(super) fn spm_tokenizer(pieces: &[(&str, f32)]) -> Gpt2Tokenizer {
+        let mut tokenizer = Gpt2Tokenizer::new();
+        tokenizer.vocab_type = VocabType::Spm;
+        for (id, &(text, score)) in pieces.iter().enumerate() {
+            tokenizer.token_to_id.insert(text.into(), id as _);
+            tokenizer.id_to_token.push(TokenData {
+                text: text.into(),
+                score,
+                attribute: TokenAttribute::Normal,
+            })
+        }
+        tokenizer
+    }
+
+    /// 只包含数字的小词表，`"12345"` 可以合并为一个词
+    pub(super) fn digits_gguf() -> MockGGuf {
+        MockGGuf::with_vocab(
+            &["1", "2", "3", "4", "5", "12", "123", "1234", "12345"],
+            &[1; 9],
+            &["1 2", "12 3", "123 4", "1234 5"],
+        )
+    }
+
+    #[test]
+    fn test_pretokenize_from_gguf_pre() {
+        let gguf = digits_gguf().str("tokenizer.ggml.pre", "gpt-2");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(
+            tokenizer.session.borrow().regex_exprs(),
+            PretokenizeKind::Gpt2.regex_exprs()
+        );
+        // gpt-2 把连续的数字切分为一个预分词片段
+        assert_eq!(tokenizer.tokenize("12345", false, false), [8]);
+
+        // qwen 每个数字单独切分
+        let tokenizer =
+            Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", "qwen2")).unwrap();
+        assert_eq!(tokenizer.tokenize("12345", false, false), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_eog_tokens() {
+        let gguf = MockGGuf::with_vocab(&["a", "b", "<|im_end|>", "ab"], &[1, 1, 3, 1], &["a b"]);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert!(tokenizer.is_eog(2));
+        assert!(!tokenizer.is_eog(3));
+        assert_eq!(tokenizer.eog_tokens(), [2])
+    }
+
+    #[test]
+    fn test_decode_out_of_range() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        assert!(tokenizer.try_get_token_data(9).is_none());
+        assert_eq!(tokenizer.decode(9), b"");
+
+        let gguf = MockGGuf::with_vocab(&["<unk>", "a"], &[2, 1], &[])
+            .u32("tokenizer.ggml.unknown_token_id", 0);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.decode(1), b"a");
+        assert_eq!(tokenizer.decode(100), b"<unk>")
+    }
+
+    #[test]
+    fn test_token_of() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        assert_eq!(tokenizer.token_of("123"), Some(6));
+        assert_eq!(tokenizer.token_of("6"), None);
+    }
+
+    #[test]
+    fn test_iter_vocab() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        let vocab = tokenizer.iter_vocab().collect::<Vec<_>>();
+        assert_eq!(vocab.len(), tokenizer.vocab_size());
+        assert!(vocab.iter().enumerate().all(|(i, (t, _))| i as u32 == *t));
+        assert_eq!(&*vocab[8].1, b"12345");
+    }
+
+    #[test]
+    fn test_spm_escape_whitespace_suffix() {
+        let mut tokenizer = Gpt2Tokenizer::new();
+        tokenizer.config.flags.add_space_prefix = true;
+        assert_eq!(tokenizer.spm_escape("hello world", true), "▁hello▁world");
+        assert_eq!(tokenizer.spm_escape("hello world", false), "hello▁world");
+
+        tokenizer.config.flags.treat_whitespace_as_suffix = true;
+        assert_eq!(tokenizer.spm_escape("hello world", true), "hello▁world▁");
+        assert_eq!(tokenizer.spm_escape("hello world", false), "hello▁world");
+    }
+
+    #[test]
+    fn test_decode_sequence_clean_spaces() {
+        let gguf = MockGGuf::with_vocab(
+            &["Hello", "\u{120},", "\u{120}world", "\u{120}!"],
+            &[1; 4],
+            &[],
+        );
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert!(tokenizer.config.flags.clean_spaces);
+        assert_eq!(tokenizer.decode_sequence(&[0, 1, 2, 3]), "Hello, world!");
+
+        tokenizer.config.flags.clean_spaces = false;
+        assert_eq!(tokenizer.decode_sequence(&[0, 1, 2, 3]), "Hello , world !");
+    }
+
+    #[test]
+    fn test_encode_with_special() {
+        let gguf = digits_gguf()
+            .str("tokenizer.ggml.pre", "gpt-2")
+            .u32("tokenizer.ggml.bos_token_id", 0)
+            .u32("tokenizer.ggml.eos_token_id", 1);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.encode_with_special("12345", false, false), [8]);
+        assert_eq!(tokenizer.encode_with_special("12345", true, false), [0, 8]);
+        assert_eq!(tokenizer.encode_with_special("12345", false, true), [8, 1]);
+        assert_eq!(
+            tokenizer.encode_with_special("12345", true, true),
+            [0, 8, 1]
+        );
+
+        // 与 encode 一样按配置决定是否识别控制标记
+        let gguf = MockGGuf::with_vocab(
+            &["1", "2", "12", "<s>", "<", "s", ">"],
+            &[1, 1, 1, 3, 1, 1, 1],
+            &["1 2"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        tokenizer.config.flags.parse_special = true;
+        assert_eq!(tokenizer.encode_with_special("12<s>", false, false), [2, 3]);
+        tokenizer.config.flags.parse_special = false;
+        assert_eq!(
+            tokenizer.encode_with_special("12<s>", false, false),
+            [2, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_try_load_gguf_model() {
+        let gguf = digits_gguf();
+        assert!(matches!(
+            Gpt2Tokenizer::try_load_gguf(&gguf),
+            Err(Gpt2Error::Meta(GGufMetaError::NotExist))
+        ));
+        let gguf = digits_gguf().str("tokenizer.ggml.model", "unknown");
+        assert!(matches!(
+            Gpt2Tokenizer::try_load_gguf(&gguf),
+            Err(Gpt2Error::UnsupportedVocab(model)) if model == "unknown"
+        ));
+        let gguf = digits_gguf().str("tokenizer.ggml.model", "gpt2");
+        assert!(Gpt2Tokenizer::try_load_gguf(&gguf).is_ok());
+
+        // 没有词表类型时不再 panic
+        assert!(
+            Gpt2Tokenizer::new()
+                .tokenize("12345", false, true)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_meta_space_from_gguf() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        assert_eq!(tokenizer.meta_space, '\u{2581}');
+
+        let gguf = digits_gguf()
+            .str("tokenizer.ggml.meta_space", "Ġ")
+            .bool("tokenizer.ggml.add_space_prefix", true);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.meta_space, 'Ġ');
+        assert_eq!(tokenizer.spm_escape("a b", true), "ĠaĠb");
+
+        let gguf =
+            MockGGuf::with_vocab(&["▁a", "Ġa"], &[1, 1], &[]).str("tokenizer.ggml.meta_space", "Ġ");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        tokenizer.vocab_type = VocabType::Spm;
+        tokenizer.config.flags.clean_spaces = false;
+        assert_eq!(tokenizer.decode_sequence(&[0, 1]), "▁a a");
+    }
+
+    #[test]
+    fn test_spm_escape_no_double_space() {
+        let mut tokenizer = Gpt2Tokenizer::new();
+        tokenizer.config.flags.add_space_prefix = true;
+        assert_eq!(tokenizer.spm_escape(" hello", true), "▁hello");
+        assert_eq!(tokenizer.spm_escape("hello", true), "▁hello");
+        assert_eq!(tokenizer.spm_escape("  hello", true), "▁▁hello");
+
+        tokenizer.config.flags.treat_whitespace_as_suffix = true;
+        assert_eq!(tokenizer.spm_escape("hello ", true), "hello▁");
+    }
+
+    #[test]
+    fn test_pretoken_cache() {
+        let gguf = digits_gguf().str("tokenizer.ggml.pre", "gpt-2");
+        let text = "12345 123 12345 12 123 12345 1234 12345".repeat(4);
+
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let uncached = tokenizer.tokenize(&text, false, false);
+        // 容量小于片段种类数，覆盖淘汰的情况
+        for capacity in [2, 16] {
+            tokenizer.set_cache_capacity(capacity);
+            assert_eq!(tokenizer.tokenize(&text, false, false), uncached);
+            assert_eq!(tokenizer.tokenize(&text, false, false), uncached);
+        }
+        tokenizer.set_pretokenize(PretokenizeKind::Gpt2);
+        assert_eq!(tokenizer.session.borrow().cache_capacity(), 16);
+    }
+
+    #[test]
+    fn test_no_special_fast_path() {
+        let gguf = MockGGuf::with_vocab(
+            &["<s>", "1", "2", "3", "12", "123"],
+            &[3, 1, 1, 1, 1, 1],
+            &["1 2", "12 3"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert!(tokenizer.needs_partition(true.into()));
+        assert!(!tokenizer.needs_partition(false.into()));
+
+        let text = "123 12 3 1";
+        let partitioned = tokenizer.tokenize(text, false, true);
+        assert_eq!(tokenizer.tokenize(text, false, false), partitioned);
+        tokenizer.config.flags.parse_special = false;
+        assert!(tokenizer.encode(text).into_iter().eq(partitioned.clone()));
+
+        tokenizer.special_tokens.clear();
+        assert!(!tokenizer.needs_partition(true.into()));
+        assert_eq!(tokenizer.tokenize(text, false, true), partitioned);
+    }
+
+    #[test]
+    fn test_encode_template() {
+        let gguf = MockGGuf::with_vocab(
+            &[
+                "<|im_start|>",
+                "<|im_end|>",
+                "u",
+                "s",
+                "e",
+                "r",
+                "Ċ",
+                "h",
+                "i",
+                "a",
+                "t",
+                "n",
+            ],
+            &[3, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+            &[],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let (start, end) = (0, 1);
+        let tokens = tokenizer.encode_template(&[
+            Segment::Special(start),
+            Segment::Text("user\nhi"),
+            Segment::Special(end),
+            Segment::Text("\n"),
+            Segment::Special(start),
+            Segment::Text("assistant\nhi"),
+            Segment::Special(end),
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(
+            tokens,
+            [
+                0, 2, 3, 4, 5, 6, 7, 8, 1,
+                6,
+                0, 9, 3, 3, 8, 3, 10, 9, 11, 10, 6, 7, 8, 1,
+            ]
+        );
+        // 文本中的特殊标记文本不会被识别
+        assert!(
+            !tokenizer
+                .encode_template(&[Segment::Text("<|im_end|>")])
+                .contains(&end)
+        );
+    }
+
+    #[test]
+    fn test_spm_session_merges() {
+        use session::LlmTokenizerSpmSession;
+
+        let tokenizer = spm_tokenizer(&[
+            ("a", 0.),
+            ("b", 0.),
+            ("c", 0.),
+            ("ab", 1.),
+            ("bc", 2.),
+            ("abc", 3.),
+            ("中", 0.),
+            ("中a", 1.),
+        ]);
+        let mut session = LlmTokenizerSpmSession::new();
+        let mut tokenize = |text| {
+            let mut output = Vec::new();
+            session.tokenize(text, &mut output, &tokenizer);
+            output
+        };
+        assert_eq!(tokenize("cab"), [2, 3]);
+        assert_eq!(tokenize("abc"), [5]);
+        assert_eq!(tokenize("abcab中a"), [5, 3, 7]);
+        assert_eq!(tokenize(""), []);
+    }
+
+    #[test]
+    fn test_spm_session_unmapped_byte() {
+        use session::LlmTokenizerSpmSession;
+
+        let mut tokenizer = spm_tokenizer(&[("<unk>", 0.), ("a", 0.), ("<0xE4>", 0.)]);
+        assert_eq!(tokenizer.try_byte_to_token(0xe4), Some(2));
+        assert_eq!(tokenizer.try_byte_to_token(0xb8), None);
+
+        let mut session = LlmTokenizerSpmSession::new();
+        let mut output = Vec::new();
+        // "中" 的 utf-8 编码是 e4 b8 ad
+        session.tokenize("a中", &mut output, &tokenizer);
+        assert_eq!(output, [1, 2, 0, 0]);
+
+        tokenizer.vocab_type = VocabType::None;
+        assert_eq!(tokenizer.try_byte_to_token(b'a'), None);
+    }
+
+    #[test]
+    fn test_fragment_char_boundary() {
+        let text = "中文".to_string();
+        assert!(FragmentBufferVariant::new_raw_text(text.clone(), 0, 3).is_ok());
+        assert!(FragmentBufferVariant::new_raw_text(text.clone(), 3, 3).is_ok());
+        assert_eq!(
+            FragmentBufferVariant::new_raw_text(text.clone(), 1, 2).err(),
+            Some("offset and length must lie on utf-8 char boundaries")
+        );
+        assert!(FragmentBufferVariant::new_raw_text(text, 0, 4).is_err());
+    }
+
+    #[test]
+    fn test_pretokenize_deepseek() {
+        for (pre, kind) in [
+            ("deepseek-llm", PretokenizeKind::DeepseekLlm),
+            ("deepseek-coder", PretokenizeKind::DeepseekCoder),
+        ] {
+            assert_eq!(PretokenizeKind::from_pre(pre), kind);
+            let tokenizer =
+                Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", pre)).unwrap();
+            assert_eq!(tokenizer.session.borrow().regex_exprs(), kind.regex_exprs());
+        }
+        assert_eq!(PretokenizeKind::DeepseekLlm.regex_exprs().len(), 6);
+        assert_eq!(
+            PretokenizeKind::DeepseekCoder.regex_exprs().last().unwrap(),
+            "\\p{N}"
+        );
+    }
+
+    #[test]
+    fn test_decode_each() {
+        let pieces = ["Hello", " world", " 中"].map(unicode::unicode_byte_encoding);
+        let mut tokenizer =
+            spm_tokenizer(&pieces.iter().map(|p| (p.as_str(), 0.)).collect::<Vec<_>>());
+        tokenizer.vocab_type = VocabType::Bpe;
+        assert_eq!(
+            tokenizer.decode_each(&[0, 1, 2]),
+            ["Hello", " world", " 中"]
+        );
+
+        let mut tokenizer = spm_tokenizer(&[("▁Hello", 0.), ("▁world", 0.)]);
+        tokenizer.vocab_type = VocabType::Spm;
+        assert_eq!(tokenizer.decode_each(&[0, 1]), [" Hello", " world"]);
+    }
+
+    #[test]
+    fn test_config() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        let encoded = tokenizer.tokenize("12345", true, true);
+        assert_eq!(tokenizer.bos(), tokenizer.config.specials.bos);
+        assert_eq!(tokenizer.add_bos(), tokenizer.config.flags.add_bos);
+
+        // 只替换配置，编码结果不变
+        let mut rebuilt = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        rebuilt.config = tokenizer.config;
+        assert_eq!(rebuilt.tokenize("12345", true, true), encoded);
+
+        // 通过配置添加 bos
+        let config = Gpt2Config {
+            specials: SpecialTokens {
+                bos: 0,
+                ..tokenizer.config.specials
+            },
+            flags: NormalizationFlags {
+                add_bos: true,
+                ..tokenizer.config.flags
+            },
+        };
+        rebuilt.config = config;
+        assert!(rebuilt.add_bos());
+        assert_eq!(
+            rebuilt.tokenize("12345", true, true),
+            [&[0], &*tokenizer.tokenize("12345", false, false)].concat()
+        );
+
+        let empty = Gpt2Tokenizer::with_config(config);
+        assert_eq!(empty.config, config);
+        assert_eq!(empty.bos(), 0);
+    }
+
+    #[test]
+    fn test_pre_decode() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        assert_eq!(&*tokenizer.pre_decode("Ġthere".as_bytes()), b" there");
+
+        let tokenizer = spm_tokenizer(&[]);
+        assert_eq!(&*tokenizer.pre_decode("▁there".as_bytes()), b" there");
+        // 非法 utf-8 原样返回
+        assert_eq!(&*tokenizer.pre_decode(&[0xff]), [0xff]);
+    }
+
+    #[test]
+    fn test_segment() {
+        let gguf = MockGGuf::with_vocab(
+            &["<|im_start|>", "<|im_end|>", "a", "b", "ab"],
+            &[3, 3, 1, 1, 1],
+            &["a b"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "<|im_start|>ab<|im_end|><|im_end|>b";
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Special(0),
+                Fragment::Text(12..14),
+                Fragment::Special(1),
+                Fragment::Special(1),
+                Fragment::Text(34..35),
+            ]
+        );
+        assert_eq!(tokenizer.tokenize(text, false, true), [0, 4, 1, 1, 3]);
+
+        tokenizer.config.flags.parse_special = false;
+        assert_eq!(tokenizer.segment(text), [Fragment::Text(0..text.len())]);
+        assert!(tokenizer.segment("").is_empty());
+    }
+
+    #[test]
+    fn test_segment_normalized() {
+        let gguf = MockGGuf::with_vocab(&["<s>", "a", "b"], &[3, 1, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        tokenizer.config.flags.parse_special = true;
+        // 全角拼写的特殊标记规范化后才能识别，范围仍是原文中的字节范围
+        let text = "a＜ｓ＞b";
+        assert_eq!(tokenizer.segment(text), [Fragment::Text(0..text.len())]);
+        tokenizer.config.flags.fullwidth_to_halfwidth = true;
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..1),
+                Fragment::Special(0),
+                Fragment::Text(10..11)
+            ]
+        );
+        assert_eq!(tokenizer.tokenize(text, false, true), [1, 0, 2]);
+
+        // 规范化结果与分词时相同
+        let flags = &mut tokenizer.config.flags;
+        flags.strip_bom = true;
+        flags.strip_zero_width = true;
+        flags.normalize_newlines = true;
+        for text in [
+            "\u{FEFF}a\r\nb",
+            "\u{FEFF}\u{FEFF}ａ\r",
+            "\r\u{200B}\n\r\r\n",
+            "\u{200B}\u{FEFF}",
+        ] {
+            let (normalized, offsets) = tokenizer.normalize_with_offsets(text);
+            assert_eq!(normalized, tokenizer.normalize_input(text));
+            assert_eq!(offsets.len(), normalized.len() + 1);
+        }
+        assert_eq!(
+            tokenizer.segment("\u{FEFF}a\r\n<s>"),
+            [Fragment::Text(3..6), Fragment::Special(0)]
+        );
+        assert!(tokenizer.segment("\u{FEFF}").is_empty());
+    }
+
+    #[test]
+    fn test_special_case_insensitive() {
+        let gguf = MockGGuf::with_vocab(&["<|im_end|>", "a", "b"], &[3, 1, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "a<|IM_END|>b";
+        assert_eq!(tokenizer.segment(text), [Fragment::Text(0..text.len())]);
+
+        tokenizer.config.flags.special_case_insensitive = true;
+        assert!(tokenizer.special_case_insensitive());
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..1),
+                Fragment::Special(0),
+                Fragment::Text(11..12)
+            ]
+        );
+        assert_eq!(tokenizer.tokenize(text, false, true), [1, 0, 2]);
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let mut tokenizer =
+            Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", "gpt-2")).unwrap();
+        assert_eq!(tokenizer.session.borrow().last_stats(), None);
+        tokenizer.session.borrow_mut().set_stats_enabled(true);
+
+        assert_eq!(tokenizer.tokenize("12345", false, false), [8]);
+        // 每次合并产生一个新的可合并二元组
+        assert_eq!(
+            tokenizer.session.borrow().last_stats(),
+            Some(SessionStats {
+                pretokens: 1,
+                cache_hits: 0,
+                bigram_pushes: 4,
+                merges: 4,
+                rank_cache_hits: 0,
+            })
+        );
+
+        // 统计只记录最近一次
+        tokenizer.set_cache_capacity(8);
+        tokenizer.tokenize("12 12", false, false);
+        let stats = tokenizer.session.borrow().last_stats().unwrap();
+        assert_eq!((stats.pretokens, stats.merges), (2, 2));
+        tokenizer.tokenize("12 12", false, false);
+        let stats = tokenizer.session.borrow().last_stats().unwrap();
+        assert_eq!((stats.cache_hits, stats.merges), (2, 0));
+    }
+
+    #[test]
+    fn test_unused_tokens() {
+        let gguf = MockGGuf::with_vocab(
+            &["a", "b", "ab", "<|eot_id|>", "<|im_end|>"],
+            &[1, 1, 5, 5, 3],
+            &["a b"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.special_tokens, [4]);
+        assert!(!tokenizer.is_eog(3));
+        assert_eq!(tokenizer.token_of("ab"), None);
+        // 合并的结果是未使用的标记时回退到单字节
+        assert_eq!(tokenizer.tokenize("ab", false, true), [0, 1]);
+        let tokens = tokenizer.tokenize("<|eot_id|>ab<|im_end|>", false, true);
+        assert!(tokens.ends_with(&[0, 1, 4]));
+        assert!(!tokens.iter().any(|&t| t == 2 || t == 3));
+        // 仍然可以解码
+        assert_eq!(tokenizer.decode(3), b"<|eot_id|>");
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        let gguf = MockGGuf::with_vocab(&["a", "b", "Ċ", "č"], &[1; 4], &[])
+            .str("tokenizer.ggml.pre", "llama3");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let lf = tokenizer.tokenize("a\nb\n", false, true);
+        assert_eq!(lf, [0, 2, 1, 2]);
+        assert_eq!(
+            tokenizer.tokenize("a\r\nb\r\n", false, true),
+            [0, 3, 2, 1, 3, 2]
+        );
+
+        tokenizer.config.flags.normalize_newlines = true;
+        assert_eq!(tokenizer.tokenize("a\r\nb\r\n", false, true), lf);
+        // 单独的 \r 保持不变
+        assert_eq!(tokenizer.tokenize("a\r", false, true), [0, 3]);
+    }
+
+    #[test]
+    fn test_linefeed_token() {
+        let gguf = MockGGuf::with_vocab(&["<s>", "a", "Ċ"], &[3, 1, 1], &[])
+            .u32("tokenizer.ggml.bos_token_id", 0)
+            .bool("tokenizer.ggml.add_bos_token", true);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.linefeed_token(), 2);
+        assert_eq!(
+            tokenizer.decode_sequence(&[tokenizer.linefeed_token()]),
+            "\n"
+        );
+
+        let tokenizer = Gpt2Tokenizer::load_gguf(&MockGGuf::with_vocab(&["a"], &[1], &[])).unwrap();
+        assert_eq!(tokenizer.linefeed_token(), NULL);
+    }
+
+    #[test]
+    fn test_pretokenize_falcon_starcoder() {
+        for (pre, kind, n) in [
+            ("falcon", PretokenizeKind::Falcon, 3),
+            ("starcoder", PretokenizeKind::Starcoder, 2),
+        ] {
+            assert_eq!(PretokenizeKind::from_pre(pre), kind);
+            let exprs = kind.regex_exprs();
+            assert_eq!(exprs.len(), n);
+            assert!(exprs.iter().any(|e| e == common::GPT2));
+            let tokenizer =
+                Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", pre)).unwrap();
+            assert_eq!(tokenizer.session.borrow().regex_exprs(), exprs);
+        }
+        // falcon 将连续的数字每 3 个切分，starcoder 每个数字单独切分
+        let tokenizer =
+            Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", "falcon")).unwrap();
+        assert_eq!(tokenizer.tokenize("12345", false, false), [6, 3, 4]);
+        let tokenizer =
+            Gpt2Tokenizer::load_gguf(&digits_gguf().str("tokenizer.ggml.pre", "starcoder"))
+                .unwrap();
+        assert_eq!(tokenizer.tokenize("12345", false, false), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_starcoder_fim_tokens() {
+        let gguf = MockGGuf::with_vocab(
+            &[
+                "<fim_prefix>",
+                "<fim_suffix>",
+                "<fim_middle>",
+                "<fim_pad>",
+                "<repo_name>",
+                "<file_sep>",
+            ],
+            &[1; 6],
+            &[],
+        );
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let specials = tokenizer.config.specials;
+        assert_eq!(
+            [
+                specials.fim_pre,
+                specials.fim_suf,
+                specials.fim_mid,
+                specials.fim_pad,
+                specials.fim_rep,
+                specials.fim_sep
+            ],
+            [0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(tokenizer.special_tokens.len(), 6);
+    }
+
+    #[test]
+    fn test_duplicate_bos_warning() {
+        let gguf = MockGGuf::with_vocab(&["<unk>", "<s>", "</s>", "a"], &[2, 3, 3, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2")
+            .u32("tokenizer.ggml.bos_token_id", 1)
+            .bool("tokenizer.ggml.add_bos_token", true);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let output = tokenizer.tokenize("<s>a<s>a<s>", true, true);
+        assert_eq!(output, [1, 1, 3, 1, 3, 1]);
+        assert!(tokenizer.duplicate_bos(&output));
+        // 文本中间的 BOS 不算重复
+        let output = tokenizer.tokenize("a<s>", true, true);
+        assert_eq!(output, [1, 3, 1]);
+        assert!(!tokenizer.duplicate_bos(&output));
+    }
+
+    #[test]
+    fn test_whitespace_only() {
+        let gguf = MockGGuf::with_vocab(
+            &["<|end|>", "Ġ", "Ċ", "ĠĠ", "ĊĊ"],
+            &[3, 1, 1, 1, 1],
+            &["Ġ Ġ", "Ċ Ċ"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2")
+        .u32("tokenizer.ggml.bos_token_id", 0)
+        .bool("tokenizer.ggml.add_bos_token", true);
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let cases: [(&str, &[TokenId]); 4] =
+            [("", &[]), (" ", &[1]), ("\n\n", &[4]), ("   ", &[3, 1])];
+        for (text, expected) in cases {
+            // 识别与不识别特殊标记的两条路径结果相同
+            for parse_special in [true, false] {
+                assert_eq!(tokenizer.tokenize(text, false, parse_special), expected);
+                assert_eq!(
+                    tokenizer.tokenize(text, true, parse_special),
+                    [&[0], expected].concat()
+                );
+            }
+        }
+        // deepseek-llm 将换行逐个切分
+        tokenizer.set_pretokenize(PretokenizeKind::DeepseekLlm);
+        assert_eq!(tokenizer.tokenize("\n\n", false, true), [2, 2]);
+        assert_eq!(tokenizer.tokenize("<|end|>", false, true), [0]);
+    }
+
+    #[test]
+    fn test_strip_multibyte() {
+        let gguf =
+            MockGGuf::with_vocab(&["<mask>", "a"], &[3, 1], &[]).str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "中文 \u{a0} <mask>  文b";
+        let mask = text.find("<mask>").unwrap();
+        let end = mask + "<mask>".len();
+
+        tokenizer.id_to_token[0].attribute = TokenAttribute::LStrIp;
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..6),
+                Fragment::Special(0),
+                Fragment::Text(end..text.len()),
+            ]
+        );
+        tokenizer.id_to_token[0].attribute = TokenAttribute::RStrIp;
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..mask),
+                Fragment::Special(0),
+                Fragment::Text(end + 2..text.len()),
+            ]
+        );
+        // 只有空白时整个片段被去除
+        assert_eq!(tokenizer.segment("<mask> \u{3000}"), [Fragment::Special(0)]);
+    }
+
+    #[test]
+    fn test_decode_into() {
+        let gguf = MockGGuf::with_vocab(&["a", "Ġ", "b", "Ġb", "ä", "¸", "Ń"], &[1; 7], &["Ġ b"])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let tokens = tokenizer.tokenize("a b中 b", false, false);
+        assert_eq!(tokens, [0, 3, 4, 5, 6, 3]);
+
+        let mut buf = Vec::new();
+        for &t in &tokens {
+            tokenizer.decode_into(t, &mut buf)
+        }
+        assert_eq!(buf, "aĠbä¸ŃĠb".as_bytes());
+        assert_eq!(
+            &*tokenizer.pre_decode(&buf),
+            tokenizer.decode_sequence(&tokens).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_bpe_space_prefix() {
+        let gguf = MockGGuf::with_vocab(
+            &["<|end|>", "Ġ", "h", "i", "Ġh", "Ġhi", "hi"],
+            &[3, 1, 1, 1, 1, 1, 1],
+            &["Ġ h", "Ġh i", "h i"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.tokenize("hi", false, false), [6]);
+
+        tokenizer.config.flags.add_space_prefix = true;
+        assert_eq!(tokenizer.tokenize("hi", false, false), [5]);
+        assert_eq!(tokenizer.tokenize("hi hi", false, false), [5, 5]);
+        // 已经以空白开头时不再添加
+        assert_eq!(tokenizer.tokenize(" hi", false, false), [5]);
+        // 识别特殊标记时只有输入开头的片段添加空格
+        assert_eq!(tokenizer.tokenize("hi<|end|>hi", false, true), [5, 0, 6]);
+        assert_eq!(tokenizer.tokenize("<|end|>hi", false, true), [0, 6]);
+    }
+
+    #[test]
+    fn test_single_word_special() {
+        let gguf = MockGGuf::with_vocab(&["<mask>", "a", "b", "_"], &[4, 1, 1, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "a<mask> <mask>b <mask>,_<mask>";
+        assert_eq!(tokenizer.segment(text).len(), 8);
+
+        tokenizer.id_to_token[0].attribute = TokenAttribute::SingleWord;
+        // 只有两侧都不是单词字符的匹配有效
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..16),
+                Fragment::Special(0),
+                Fragment::Text(22..text.len()),
+            ]
+        );
+        assert_eq!(tokenizer.segment("<mask>"), [Fragment::Special(0)]);
+        assert_eq!(tokenizer.segment("中<mask>"), [Fragment::Text(0..9)]);
+    }
+
+    #[test]
+    fn test_normalized_special() {
+        let gguf = MockGGuf::with_vocab(&["Ġ<sep>Ġ", "a", "Ġ"], &[4, 1, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "a <sep> a";
+        assert_eq!(tokenizer.segment(text), [Fragment::Text(0..text.len())]);
+
+        // 规范化的标记与还原字节映射后的文本 " <sep> " 比较
+        tokenizer.id_to_token[0].attribute = TokenAttribute::Normalized;
+        assert_eq!(
+            tokenizer.segment(text),
+            [
+                Fragment::Text(0..1),
+                Fragment::Special(0),
+                Fragment::Text(8..9)
+            ]
+        );
+        assert_eq!(tokenizer.tokenize(text, false, true), [1, 0, 1]);
+    }
+
+    #[test]
+    fn test_strip_bom_and_zero_width() {
+        // 词表包含 BOM（EF BB BF）和零宽空格（E2 80 8B）的字节
+        let vocab = ["a", "b", "ab", "ï", "»", "¿", "â", "Ģ", "ĭ"];
+        let gguf =
+            MockGGuf::with_vocab(&vocab, &[1; 9], &["a b"]).str("tokenizer.ggml.pre", "llama3");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let plain = tokenizer.tokenize("abab", false, true);
+        assert_eq!(plain, [2, 2]);
+        // 默认不处理，BOM 和零宽字符产生多余的词
+        assert_eq!(
+            tokenizer.tokenize("\u{FEFF}abab", false, true),
+            [3, 4, 5, 2, 2]
+        );
+        assert_ne!(tokenizer.tokenize("a\u{200B}bab", false, true), plain);
+
+        tokenizer.config.flags.strip_bom = true;
+        assert_eq!(tokenizer.tokenize("\u{FEFF}abab", false, true), plain);
+        // 只去掉开头的 BOM
+        assert_ne!(tokenizer.tokenize("ab\u{FEFF}ab", false, true), plain);
+
+        tokenizer.config.flags.strip_zero_width = true;
+        for zw in ['\u{200B}', '\u{200C}', '\u{200D}'] {
+            assert_eq!(tokenizer.tokenize(&format!("a{zw}bab"), false, true), plain);
+        }
+    }
+
+    #[test]
+    fn test_adjacent_specials() {
+        let gguf = MockGGuf::with_vocab(&["<|a|>", "<|b|>", "x", "Ġ"], &[3, 3, 1, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        use Fragment::*;
+        // 相邻的特殊标记之间没有空的文本片段
+        assert_eq!(tokenizer.segment("<|a|><|b|>"), [Special(0), Special(1)]);
+        assert_eq!(
+            tokenizer.segment("<|a|><|a|><|b|>x<|b|><|b|>"),
+            [
+                Special(0),
+                Special(0),
+                Special(1),
+                Text(15..16),
+                Special(1),
+                Special(1)
+            ]
+        );
+        assert_eq!(
+            tokenizer.tokenize("<|a|><|b|><|a|>", false, true),
+            [0, 1, 0]
+        );
+
+        // 去除空白后为空的片段也不输出
+        tokenizer.id_to_token[1].attribute = TokenAttribute::LStrIp;
+        assert_eq!(tokenizer.segment("<|a|> <|b|>"), [Special(0), Special(1)]);
+        tokenizer.id_to_token[0].attribute = TokenAttribute::RStrIp;
+        assert_eq!(tokenizer.segment("<|a|>  <|a|>"), [Special(0), Special(0)]);
+    }
+
+    #[test]
+    fn test_load_gguf_errors() {
+        let load = |gguf: MockGGuf| Gpt2Tokenizer::load_gguf(&gguf).map(|_| ());
+        assert!(load(digits_gguf()).is_ok());
+        assert!(matches!(
+            load(digits_gguf().remove("tokenizer.ggml.tokens")),
+            Err(Gpt2Error::MissingTokens)
+        ));
+        assert!(matches!(
+            load(digits_gguf().remove("tokenizer.ggml.token_type")),
+            Err(Gpt2Error::MissingTokenType)
+        ));
+        assert!(matches!(
+            load(digits_gguf().remove("tokenizer.ggml.merges")),
+            Err(Gpt2Error::MissingMerges)
+        ));
+        assert!(matches!(
+            load(digits_gguf().str_arr("tokenizer.ggml.merges", &["1 2", "12"])),
+            Err(Gpt2Error::InvalidMerge(merge)) if merge == "12"
+        ));
+        assert!(matches!(
+            load(digits_gguf().str("tokenizer.ggml.model", "unknown")),
+            Err(Gpt2Error::UnsupportedVocab(model)) if model == "unknown"
+        ));
+        // 数组长度与词表不同
+        assert!(matches!(
+            load(digits_gguf().i32_arr("tokenizer.ggml.token_type", &[1; 8])),
+            Err(Gpt2Error::LengthMismatch("tokenizer.ggml.token_type"))
+        ));
+        assert!(matches!(
+            load(digits_gguf().f32_arr("tokenizer.ggml.scores", &[0.; 10])),
+            Err(Gpt2Error::LengthMismatch("tokenizer.ggml.scores"))
+        ));
+        assert!(load(digits_gguf().f32_arr("tokenizer.ggml.scores", &[0.; 9])).is_ok());
+        // 类型错误的元信息
+        assert!(matches!(
+            load(digits_gguf().str("tokenizer.ggml.tokens", "1")),
+            Err(Gpt2Error::Meta(_))
+        ));
+    }
+
+    #[test]
+    fn test_fullwidth_to_halfwidth() {
+        let gguf = MockGGuf::with_vocab(
+            &["A", "b", "Ġ", "1", "Ab", "ï", "¼", "¡"],
+            &[1; 8],
+            &["A b"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let plain = tokenizer.tokenize("Ab 1", false, true);
+        assert_eq!(plain, [4, 2, 3]);
+        // 默认不转换，Ａ（EF BC A1）逐字节编码
+        assert_eq!(tokenizer.tokenize("Ａ", false, true), [5, 6, 7]);
+
+        tokenizer.config.flags.fullwidth_to_halfwidth = true;
+        assert_eq!(
+            tokenizer.tokenize("Ａ", false, true),
+            tokenizer.tokenize("A", false, true)
+        );
+        assert_eq!(tokenizer.tokenize("Ａｂ\u{3000}１", false, true), plain);
+        // 其他字符保持不变
+        assert_eq!(halfwidth('中'), '中');
+        assert_eq!(halfwidth('～'), '~');
+    }
+
+    #[test]
+    fn test_rank_cache() {
+        let gguf = digits_gguf().str("tokenizer.ggml.pre", "gpt-2");
+        let text = "12345 123 12345 12 123 12345 1234 12345".repeat(4);
+
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let uncached = tokenizer.tokenize(&text, false, false);
+        tokenizer.set_rank_cache(true);
+        tokenizer.session.borrow_mut().set_stats_enabled(true);
+        assert_eq!(tokenizer.tokenize(&text, false, false), uncached);
+        let stats = tokenizer.session.borrow().last_stats().unwrap();
+        assert!(stats.rank_cache_hits > 0);
+        // 缓存在多次分词之间保留，初始的 4 个二元组和合并后新产生的 3 个二元组都命中缓存
+        assert_eq!(tokenizer.tokenize("12345", false, false), [8]);
+        let stats = tokenizer.session.borrow().last_stats().unwrap();
+        assert_eq!(stats.rank_cache_hits, 7);
+
+        tokenizer.set_pretokenize(PretokenizeKind::Gpt2);
+        assert!(tokenizer.session.borrow().rank_cache_enabled());
+    }
+
+    #[test]
+    fn test_detokenize() {
+        let vocab = ["a", "Ġ", "b", "Ġb", "ä", "¸", "Ń", "<s>", ",", "Ġ,"];
+        let gguf = MockGGuf::with_vocab(&vocab, &[1, 1, 1, 1, 1, 1, 1, 3, 1, 1], &["Ġ b"])
+            .str("tokenizer.ggml.pre", "gpt-2")
+            .u32("tokenizer.ggml.bos_token_id", 7)
+            .bool("tokenizer.ggml.add_bos_token", true);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "a b中 b , b";
+        let tokens = tokenizer.tokenize(text, true, false);
+        assert_eq!(tokens[0], 7);
+        assert_eq!(tokenizer.detokenize(&tokens, true, false), text);
+        assert_eq!(
+            tokenizer.detokenize(&tokens, false, false),
+            format!("<s>{text}")
+        );
+        // 清理标点前的空格
+        assert_eq!(tokenizer.detokenize(&tokens, true, true), "a b中 b, b");
+    }
+
+    #[test]
+    fn test_special_parse_mode() {
+        let gguf = MockGGuf::with_vocab(&["<c>", "<u>", "a"], &[3, 4, 1], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        let text = "<c>a<u>";
+        let tokenize = |mode| tokenizer.tokenize_with_mode(text, false, mode);
+        // 未识别的特殊标记按普通文本逐字符编码，词表中没有 '<' '>' 等字符，只剩下 'a'
+        assert_eq!(tokenize(SpecialParseMode::All), [0, 2, 1]);
+        assert_eq!(tokenize(SpecialParseMode::UserDefined), [2, 1]);
+        assert_eq!(tokenize(SpecialParseMode::Control), [0, 2]);
+        assert_eq!(tokenize(SpecialParseMode::None), [2]);
+        // bool 开关对应两种模式
+        assert_eq!(tokenizer.tokenize(text, false, true), [0, 2, 1]);
+        assert_eq!(tokenizer.tokenize(text, false, false), [2, 1]);
+    }
+
+    #[test]
+    fn test_load_gguf_vocab_type() {
+        for (model, vocab_type) in [
+            ("no_vocab", VocabType::None),
+            ("llama", VocabType::Spm),
+            ("gpt2", VocabType::Bpe),
+        ] {
+            let gguf = digits_gguf().str("tokenizer.ggml.model", model);
+            let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+            assert_eq!(tokenizer.vocab_type, vocab_type, "{model}");
+            // 只有 BPE 词表需要合词规则
+            let gguf = gguf.remove("tokenizer.ggml.merges");
+            assert_eq!(
+                Gpt2Tokenizer::load_gguf(&gguf).is_ok(),
+                vocab_type != VocabType::Bpe,
+                "{model}"
+            );
+        }
+        // 缺少词表类型时视为 gpt2
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        assert_eq!(tokenizer.vocab_type, VocabType::Bpe);
+
+        // SPM 词表使用 llama 的默认特殊标记并在开头添加空格
+        let gguf = digits_gguf().str("tokenizer.ggml.model", "llama");
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(
+            (tokenizer.bos(), tokenizer.eos(), tokenizer.unk()),
+            (1, 2, 0)
+        );
+        assert!(tokenizer.add_space_prefix());
+
+        // 分词尚未实现的词表类型不被接受
+        for model in ["bert", "t5", "rwkv"] {
+            let gguf = digits_gguf().str("tokenizer.ggml.model", model);
+            assert!(
+                matches!(Gpt2Tokenizer::load_gguf(&gguf), Err(Gpt2Error::UnsupportedVocab(m)) if m == model)
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_gguf_vocab_type_encode() {
+        let tokenize = |model, tokens: &[&str], types: &[i32], merges: &[&str], text| {
+            let gguf = MockGGuf::with_vocab(tokens, types, merges)
+                .str("tokenizer.ggml.model", model)
+                .bool("tokenizer.ggml.add_bos_token", false);
+            Gpt2Tokenizer::load_gguf(&gguf)
+                .unwrap()
+                .tokenize(text, false, false)
+        };
+        assert_eq!(tokenize("no_vocab", &["a"], &[1], &[], "a"), []);
+        assert_eq!(
+            tokenize(
+                "llama",
+                &["<unk>", "\u{2581}", "a", "b", "\u{2581}a", "\u{2581}ab"],
+                &[2, 1, 1, 1, 1, 1],
+                &[],
+                "ab a"
+            ),
+            [5, 4]
+        );
+        assert_eq!(
+            tokenize("gpt2", &["a", "b", "ab"], &[1; 3], &["a b"], "abab"),
+            [2, 2]
+        );
+    }
+
+    #[test]
+    fn test_tokeneer_decode_partial_char() {
+        // "中" 的 utf-8 编码 e4 b8 ad 按字节映射为 "ä¸" 和 "Ń" 两个词
+        let gguf = MockGGuf::with_vocab(&["\u{e4}\u{b8}", "\u{143}"], &[1; 2], &[]);
+        let tokeneer = crate::Tokeneer::new(Gpt2Tokenizer::load_gguf(&gguf).unwrap());
+        assert_eq!(tokeneer.decode(&[0, 1]), "中");
+        assert_eq!(tokeneer.decode(&[0]), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_decode_lossless() {
+        let tokenizer = Gpt2Tokenizer::load_gguf(&digits_gguf()).unwrap();
+        // gpt2 词表默认没有 unk
+        assert_eq!(tokenizer.unk_token(), NULL);
+        assert_eq!(tokenizer.decode_lossless(&[5, 2]).unwrap(), b"123");
+        assert_eq!(tokenizer.decode_lossless(&[5, 99]), None);
+        assert_eq!(tokenizer.decode_lossless(&[NULL]), None);
+
+        let gguf = digits_gguf().u32("tokenizer.ggml.unknown_token_id", 0);
+        let tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.unk_token(), 0);
+        assert_eq!(tokenizer.decode_lossless(&[0, 5]), None);
+        assert_eq!(tokenizer.decode_lossless(&[5]).unwrap(), b"12");
+    }
+
+    #[test]
+    fn test_tokeneer_encode_full_byte_level() {
+        let gguf = MockGGuf::with_vocab(
+            &["Ġ", "a", "Ġa", "b", "ä", "¸", "Ń", "ä¸", "ä¸Ń", "<|end|>"],
+            &[1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+            &["Ġ a", "ä ¸", "ä¸ Ń"],
+        )
+        .str("tokenizer.ggml.pre", "gpt-2")
+        .bool("tokenizer.ggml.add_bos_token", false);
+        let tokeneer = crate::Tokeneer::new(Gpt2Tokenizer::load_gguf(&gguf).unwrap());
+        // 范围按还原字节映射后的长度计算，" a" 是 2 个字节，"中" 是 3 个字节
+        let text = "b a中<|end|>a";
+        let encoding = tokeneer.encode_full(text);
+        assert_eq!(encoding.tokens, [3, 2, 8, 9, 1]);
+        assert_eq!(encoding.offsets, [0..1, 1..3, 3..6, 6..13, 13..14]);
+        assert_eq!(encoding.special_mask, [false, false, false, true, false]);
+        assert_eq!(encoding.tokens, tokeneer.encode(text));
+    }
+
+    #[test]
+    fn test_tokeneer_encode_full_bos() {
+        let gguf = digits_gguf()
+            .str("tokenizer.ggml.pre", "gpt-2")
+            .u32("tokenizer.ggml.bos_token_id", 0)
+            .bool("tokenizer.ggml.add_bos_token", true);
+        let tokeneer = crate::Tokeneer::new(Gpt2Tokenizer::load_gguf(&gguf).unwrap());
+        // 自动添加的 bos 是特殊标记，范围为空
+        let encoding = tokeneer.encode_full("12");
+        assert_eq!(encoding.tokens, [0, 5]);
+        assert_eq!(encoding.offsets, [0..0, 0..2]);
+        assert_eq!(encoding.special_mask, [true, false]);
+        assert_eq!(encoding.tokens, tokeneer.encode("12"));
+    }
+
+    #[test]
+    fn test_tokeneer_encode_full_spm() {
+        let gguf = MockGGuf::with_vocab(
+            &[
+                "<unk>",
+                "<s>",
+                "</s>",
+                "\u{2581}",
+                "a",
+                "b",
+                "\u{2581}a",
+                "\u{2581}ab",
+            ],
+            &[2, 3, 3, 1, 1, 1, 1, 1],
+            &[],
+        )
+        .str("tokenizer.ggml.model", "llama")
+        .bool("tokenizer.ggml.add_bos_token", true)
+        .bool("tokenizer.ggml.add_eos_token", false);
+        let tokeneer = crate::Tokeneer::new(Gpt2Tokenizer::load_gguf(&gguf).unwrap());
+        // 每段开头添加的空格不计入范围
+        let text = "ab</s>ab b";
+        let encoding = tokeneer.encode_full(text);
+        assert_eq!(encoding.tokens, [1, 7, 2, 1, 7, 3, 5]);
+        assert_eq!(
+            encoding.offsets,
+            [0..0, 0..2, 2..6, 6..6, 6..8, 8..9, 9..10]
+        );
+        assert_eq!(
+            encoding.special_mask,
+            [true, false, true, true, false, false, false]
+        );
+        assert_eq!(encoding.tokens, tokeneer.encode(text));
+    }
+
+    #[test]
+    fn test_ignore_merges() {
+        let gguf = MockGGuf::with_vocab(&["1", "2", "3", "123"], &[1; 4], &[])
+            .str("tokenizer.ggml.pre", "gpt-2");
+        let mut tokenizer = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.tokenize("123", false, false), [0, 1, 2]);
+        // 在词表中的单词直接输出，不在词表中的单词仍然执行合并
+        tokenizer.config.flags.ignore_merges = true;
+        assert_eq!(tokenizer.tokenize("123", false, false), [3]);
+        assert_eq!(tokenizer.tokenize("1", false, false), [0]);
+        assert_eq!(tokenizer.tokenize("1232", false, false), [0, 1, 2, 1]);
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
     cmp::{Ordering, Reverse},
-    collections::{BinaryHeap, HashMap, VecDeque},
+    collections::{BTreeMap, BinaryHeap, HashMap, VecDeque},
 };
 
 use super::{
     Gpt2Tokenizer,
-    common::{NULL, TokenId},
-    unicode::{unicode_len_utf8, unicode_regex_split},
+    common::{DEEPSEEK_CODER, DEEPSEEK_LLM, FALCON, GPT2, LLAMA3, NULL, QWEN, STARCODER, TokenId},
+    unicode::{
+        unicode_byte_encoding, unicode_len_utf8, unicode_merge_graphemes, unicode_regex_split_iter,
+        unicode_regex_split_ranges,
+    },
 };
 
 /// 符号结构体，表示文本中的一个符号
@@ -29,10 +32,32 @@ pub struct LlmTokenizerBpeSession {
     tokenizer: LlmTokenizerBpe,
     /// 符号列表
     symbols: Vec<LlmSymbol>,
-    /// 最终符号列表
-    symbols_final: Vec<LlmSymbol>,
     /// 工作队列
     work_queue: LlmBigramBpe,
+    /// 预分词片段到标记序列的缓存
+    cache: Option<PretokenCache>,
+    /// 最近一次分词的统计，`None` 表示不统计
+    stats: Option<SessionStats>,
+    /// 二元组到合并排名的缓存，在多次分词之间保留，`None` 表示不缓存
+    rank_cache: Option<HashMap<(String, String), i32>>,
+}
+
+/// 排名缓存的容量上限，超过时清空重新缓存
+const RANK_CACHE_CAPACITY: usize = 1 << 16;
+
+/// 一次分词的开销统计
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SessionStats {
+    /// 处理的预分词片段数
+    pub pretokens: usize,
+    /// 命中缓存的预分词片段数
+    pub cache_hits: usize,
+    /// 加入工作队列的二元组数
+    pub bigram_pushes: usize,
+    /// 执行的合并数
+    pub merges: usize,
+    /// 命中排名缓存的二元组数
+    pub rank_cache_hits: usize,
 }
 
 impl LlmTokenizerBpeSession {
@@ -41,11 +66,58 @@ impl LlmTokenizerBpeSession {
         Self {
             tokenizer,
             symbols: Vec::new(),
-            symbols_final: Vec::new(),
             work_queue: LlmBigramBpe::new(),
+            cache: None,
+            stats: None,
+            rank_cache: None,
         }
     }
 
+    /// 当前使用的预分词正则表达式
+    pub fn regex_exprs(&self) -> &[String] {
+        &self.tokenizer.regex_exprs
+    }
+
+    /// 预分词时是否保持字素簇完整
+    pub fn split_on_graphemes(&self) -> bool {
+        self.tokenizer.split_on_graphemes
+    }
+
+    /// 设置预分词时是否保持字素簇完整
+    pub fn set_split_on_graphemes(&mut self, val: bool) {
+        self.tokenizer.split_on_graphemes = val
+    }
+
+    /// 缓存容量，0 表示不缓存
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.as_ref().map_or(0, |cache| cache.capacity)
+    }
+
+    /// 设置缓存容量，0 表示关闭缓存，会清空已有的缓存
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache = (capacity > 0).then(|| PretokenCache::new(capacity))
+    }
+
+    /// 排名缓存是否开启
+    pub fn rank_cache_enabled(&self) -> bool {
+        self.rank_cache.is_some()
+    }
+
+    /// 开启或关闭二元组排名的缓存，会清空已有的缓存
+    pub fn set_rank_cache_enabled(&mut self, enabled: bool) {
+        self.rank_cache = enabled.then(HashMap::new)
+    }
+
+    /// 开启或关闭分词统计，开启时清空已有的统计
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = enabled.then(SessionStats::default)
+    }
+
+    /// 最近一次调用 [`tokenize`](Self::tokenize) 的统计，没有开启统计时为 `None`
+    pub fn last_stats(&self) -> Option<SessionStats> {
+        self.stats
+    }
+
     /// 添加标记到输出
     pub fn append(token_id: TokenId, output: &mut Vec<TokenId>) {
         output.push(token_id);
@@ -53,128 +125,130 @@ impl LlmTokenizerBpeSession {
 
     /// 标记化文本
     pub fn tokenize(&mut self, text: &str, output: &mut Vec<TokenId>, config: &Gpt2Tokenizer) {
-        let mut final_prev_index = -1;
-        let word_collection = unicode_regex_split(text, &self.tokenizer.regex_exprs);
-        self.symbols_final.clear();
-
-        for word in word_collection {
-            self.work_queue = LlmBigramBpe::new();
-            self.symbols.clear();
-            // 如果词汇表忽略合并且单词已经在词汇表中
-            if config.ignore_merges && config.text_to_token(&word) != NULL {
-                todo!();
-                self.symbols.push(LlmSymbol {
-                    prev: -1,
-                    next: -1,
-                    text: word.to_string(),
-                    n: word.len(),
-                });
+        // 逐个取出预分词片段时需要可变借用会话，暂时取出正则表达式，分词结束后放回
+        let regex_exprs = std::mem::take(&mut self.tokenizer.regex_exprs);
+        let merged;
+        let pieces: Box<dyn Iterator<Item = &str>> = if self.tokenizer.split_on_graphemes {
+            merged = unicode_merge_graphemes(text, unicode_regex_split_ranges(text, &regex_exprs));
+            Box::new(merged.iter().map(|range| &text[range.clone()]))
+        } else {
+            Box::new(unicode_regex_split_iter(text, &regex_exprs))
+        };
+        if let Some(stats) = &mut self.stats {
+            *stats = SessionStats::default()
+        }
+        for piece in pieces {
+            if let Some(stats) = &mut self.stats {
+                stats.pretokens += 1
             }
-
-            // 将单词分割为 UTF-8 字符
-            for (i, c) in word.chars().enumerate() {
-                let sym = LlmSymbol {
-                    text: c.to_string(),
-                    n: c.len_utf8(),
-                    prev: i as i32 - 1,
-                    next: if i == word.chars().count() - 1 {
-                        -1
-                    } else {
-                        i as i32 + 1
-                    },
-                };
-                self.symbols.push(sym);
+            let word = unicode_byte_encoding(piece);
+            if let Some(tokens) = self.cache.as_mut().and_then(|cache| cache.get(&word)) {
+                output.extend_from_slice(tokens);
+                if let Some(stats) = &mut self.stats {
+                    stats.cache_hits += 1
+                }
+                continue;
+            }
+            let start = output.len();
+            self.tokenize_word(&word, output, config);
+            if let Some(cache) = &mut self.cache {
+                cache.put(word, output[start..].to_vec())
             }
+        }
+        self.tokenizer.regex_exprs = regex_exprs
+    }
 
-            // 添加所有可能的二元组
-            for i in 1..(self.symbols.len() as i32) {
-                self.add_new_bigram(i - 1, i, config);
+    /// 对单个预分词片段执行合并并输出标记
+    fn tokenize_word(&mut self, word: &str, output: &mut Vec<TokenId>, config: &Gpt2Tokenizer) {
+        self.work_queue = LlmBigramBpe::new();
+        self.symbols.clear();
+        // 如果词汇表忽略合并且单词已经在词汇表中，整个单词作为一个标记，跳过合并
+        if config.ignore_merges() {
+            let token = config.text_to_token(word);
+            if token != NULL {
+                output.push(token);
+                return;
             }
-            // 构建标记
-            while let Some(bigram) = self.work_queue.pop_move() {
-                let left_idx = bigram.left as usize;
-                let right_idx = bigram.right as usize;
-
-                // 获取左右符号的引用
-                let left_symbol = &self.symbols[left_idx];
-                let right_symbol = &self.symbols[right_idx];
-                let flag = format!("{}{}", &left_symbol.text, &right_symbol.text);
-
-                // 如果其中一个符号已经被合并，跳过它
-                if left_symbol.n == 0 || right_symbol.n == 0 {
-                    continue;
-                }
+        }
 
-                // 检查二元组是否过时
-                if flag != bigram.text {
-                    continue;
-                }
+        // 将单词分割为 UTF-8 字符
+        for (i, c) in word.chars().enumerate() {
+            let sym = LlmSymbol {
+                text: c.to_string(),
+                n: c.len_utf8(),
+                prev: i as i32 - 1,
+                next: if i == word.chars().count() - 1 {
+                    -1
+                } else {
+                    i as i32 + 1
+                },
+            };
+            self.symbols.push(sym);
+        }
 
-                // 合并右符号到左符号
-                self.symbols[left_idx].n += self.symbols[right_idx].n;
+        // 添加所有可能的二元组
+        for i in 1..(self.symbols.len() as i32) {
+            self.add_new_bigram(i - 1, i, config);
+        }
+        // 构建标记
+        while let Some(bigram) = self.work_queue.pop_move() {
+            let left_idx = bigram.left as usize;
+            let right_idx = bigram.right as usize;
 
-                // 将右符号标记为已合并
-                self.symbols[right_idx].n = 0;
+            // 获取左右符号的引用
+            let left_symbol = &self.symbols[left_idx];
+            let right_symbol = &self.symbols[right_idx];
+            let flag = format!("{}{}", &left_symbol.text, &right_symbol.text);
 
-                // 从链中移除右符号
-                let right_next = self.symbols[right_idx].next;
-                self.symbols[left_idx].next = right_next;
-                self.symbols[left_idx].text = flag;
-                if right_next >= 0 {
-                    self.symbols[right_next as usize].prev = bigram.left;
-                }
-                // 寻找更多合并
-                self.add_new_bigram(self.symbols[left_idx].prev, bigram.left, config);
-                self.add_new_bigram(bigram.left, self.symbols[left_idx].next, config);
+            // 如果其中一个符号已经被合并，跳过它
+            if left_symbol.n == 0 || right_symbol.n == 0 {
+                continue;
+            }
+
+            // 检查二元组是否过时
+            if flag != bigram.text {
+                continue;
             }
 
-            // 将完成的标记添加到最终列表，保持正确的顺序
-            for sym in &self.symbols {
-                if sym.n > 0 {
-                    let mut new_sym = sym.clone();
-                    new_sym.prev = final_prev_index;
-                    new_sym.next = -1;
+            if let Some(stats) = &mut self.stats {
+                stats.merges += 1
+            }
+            // 合并右符号到左符号
+            self.symbols[left_idx].n += self.symbols[right_idx].n;
 
-                    if final_prev_index != -1 {
-                        self.symbols_final[final_prev_index as usize].next =
-                            self.symbols_final.len() as i32;
-                    }
+            // 将右符号标记为已合并
+            self.symbols[right_idx].n = 0;
 
-                    self.symbols_final.push(new_sym);
-                    final_prev_index = (self.symbols_final.len() - 1) as i32;
-                }
+            // 从链中移除右符号
+            let right_next = self.symbols[right_idx].next;
+            self.symbols[left_idx].next = right_next;
+            self.symbols[left_idx].text = flag;
+            if right_next >= 0 {
+                self.symbols[right_next as usize].prev = bigram.left;
             }
+            // 寻找更多合并
+            self.add_new_bigram(self.symbols[left_idx].prev, bigram.left, config);
+            self.add_new_bigram(bigram.left, self.symbols[left_idx].next, config);
         }
 
-        // 使用最终符号列表
-        self.symbols = self.symbols_final.clone();
-
-        // 处理所有符号
-        if !self.symbols.is_empty() {
-            let mut i = 0;
-            while i != -1 {
-                let symbol = &self.symbols[i as usize];
-                if symbol.n > 0 {
-                    // 创建符号的字符串
-                    let str =
-                        String::from_utf8_lossy(&symbol.text.as_bytes()[..symbol.n]).to_string();
-                    let token = config.text_to_token(&str);
-
-                    if token == NULL {
-                        // 如果找不到标记，将每个字节作为单独的标记输出
-                        for byte in str.bytes() {
-                            let byte_str = String::from(byte as char);
-                            let token_multibyte = config.text_to_token(&byte_str);
-                            if token_multibyte != NULL {
-                                output.push(token_multibyte);
-                            }
-                        }
-                    } else {
-                        // 添加找到的标记
-                        output.push(token);
+        // 合并总是并入左符号，未被合并的符号按原顺序排列
+        for symbol in self.symbols.iter().filter(|sym| sym.n > 0) {
+            // 创建符号的字符串
+            let str = String::from_utf8_lossy(&symbol.text.as_bytes()[..symbol.n]).to_string();
+            let token = config.text_to_token(&str);
+
+            if token == NULL {
+                // 如果找不到标记，将每个字节作为单独的标记输出
+                for byte in str.bytes() {
+                    let byte_str = String::from(byte as char);
+                    let token_multibyte = config.text_to_token(&byte_str);
+                    if token_multibyte != NULL {
+                        output.push(token_multibyte);
                     }
                 }
-                i = symbol.next;
+            } else {
+                // 添加找到的标记
+                output.push(token);
             }
         }
     }
@@ -188,7 +262,28 @@ impl LlmTokenizerBpeSession {
         let left_token = &self.symbols[left as usize].text;
         let right_token = &self.symbols[right as usize].text;
 
-        let rank_found = config.find_bpe_rank(left_token, right_token);
+        let rank_found = match &mut self.rank_cache {
+            Some(cache) => {
+                let key = (left_token.clone(), right_token.clone());
+                match cache.get(&key) {
+                    Some(&rank) => {
+                        if let Some(stats) = &mut self.stats {
+                            stats.rank_cache_hits += 1
+                        }
+                        rank
+                    }
+                    None => {
+                        let rank = config.find_bpe_rank(left_token, right_token);
+                        if cache.len() >= RANK_CACHE_CAPACITY {
+                            cache.clear()
+                        }
+                        cache.insert(key, rank);
+                        rank
+                    }
+                }
+            }
+            None => config.find_bpe_rank(left_token, right_token),
+        };
 
         if rank_found < 0 {
             return;
@@ -202,10 +297,54 @@ impl LlmTokenizerBpeSession {
             rank: rank_found,
         };
 
+        if let Some(stats) = &mut self.stats {
+            stats.bigram_pushes += 1
+        }
         self.work_queue.push(bigram);
     }
 }
 
+/// 有界的 LRU 缓存，记录预分词片段的编码结果
+struct PretokenCache {
+    capacity: usize,
+    /// 片段 -> (标记序列, 最近访问时刻)
+    map: HashMap<String, (Vec<TokenId>, u64)>,
+    /// 访问时刻 -> 片段，最小的时刻最久未使用
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl PretokenCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            map: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, word: &str) -> Option<&[TokenId]> {
+        let (tokens, last) = self.map.get_mut(word)?;
+        let key = self.order.remove(last).unwrap();
+        self.tick += 1;
+        *last = self.tick;
+        self.order.insert(self.tick, key);
+        Some(tokens)
+    }
+
+    fn put(&mut self, word: String, tokens: Vec<TokenId>) {
+        if self.map.len() >= self.capacity
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            self.map.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, word.clone());
+        self.map.insert(word, (tokens, self.tick));
+    }
+}
+
 /// BPE 二元组项结构体
 #[derive(Clone, Debug)]
 pub struct LlmBigramBpeItem {
@@ -280,6 +419,67 @@ impl LlmBigramBpe {
 pub struct LlmTokenizerBpe {
     /// 正则表达式列表
     pub regex_exprs: Vec<String>,
+    /// 不在扩展字素簇内部切分，使 emoji 序列和组合字符保持在同一个片段中
+    pub split_on_graphemes: bool,
+}
+
+impl LlmTokenizerBpe {
+    /// 使用指定的预分词规则创建 BPE 标记器
+    pub fn new(kind: PretokenizeKind) -> Self {
+        Self {
+            regex_exprs: kind.regex_exprs(),
+            split_on_graphemes: false,
+        }
+    }
+}
+
+/// 预分词规则，决定 BPE 合并之前如何用正则表达式切分文本
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PretokenizeKind {
+    Gpt2,
+    Llama3,
+    Qwen,
+    DeepseekLlm,
+    DeepseekCoder,
+    Falcon,
+    Starcoder,
+    Custom(Vec<String>),
+}
+
+impl PretokenizeKind {
+    /// 根据 gguf 中的 `tokenizer.ggml.pre` 选择预分词规则
+    ///
+    /// 无法识别的值沿用 Qwen 的规则。
+    pub fn from_pre(pre: &str) -> Self {
+        match pre {
+            "gpt-2" | "phi-2" | "jina-es" | "jina-de" | "jina-v1-en" | "jina-v2-es"
+            | "jina-v2-de" | "jina-v2-code" | "gigachat" => Self::Gpt2,
+            "llama3" | "llama-v3" | "llama-bpe" | "falcon3" => Self::Llama3,
+            "qwen2" | "deepseek-r1-qwen" => Self::Qwen,
+            "deepseek-llm" => Self::DeepseekLlm,
+            "deepseek-coder" => Self::DeepseekCoder,
+            "falcon" => Self::Falcon,
+            "starcoder" => Self::Starcoder,
+            _ => {
+                log::warn!("unknown tokenizer.ggml.pre: {pre}, fallback to qwen2");
+                Self::Qwen
+            }
+        }
+    }
+
+    /// 预分词规则对应的正则表达式列表
+    pub fn regex_exprs(&self) -> Vec<String> {
+        match self {
+            Self::Gpt2 => vec![GPT2.to_string()],
+            Self::Llama3 => vec![LLAMA3.to_string()],
+            Self::Qwen => vec![QWEN.to_string()],
+            Self::DeepseekLlm => DEEPSEEK_LLM.iter().map(|s| s.to_string()).collect(),
+            Self::DeepseekCoder => DEEPSEEK_CODER.iter().map(|s| s.to_string()).collect(),
+            Self::Falcon => FALCON.iter().map(|s| s.to_string()).collect(),
+            Self::Starcoder => STARCODER.iter().map(|s| s.to_string()).collect(),
+            Self::Custom(exprs) => exprs.clone(),
+        }
+    }
 }
 /// 二元组结构体，用于表示两个相邻的符号
 #[derive(Clone, Debug)]
@@ -352,15 +552,19 @@ impl<'a> LlmTokenizerSpmSession {
         let mut offs = 0;
 
         self.symbols.clear();
+        self.work_queue.clear();
+        self.rev_merge.clear();
 
         while offs < text.len() {
             // 获取当前字符的 UTF-8 长度
             let len = unicode_len_utf8(text.as_bytes()[offs]);
 
             // 创建新的符号
+            // 每个符号只保存自身的片段，合并时拼接，保证 text[..n] 总是符号自己的内容
+            let n = std::cmp::min(len, text.len() - offs);
             let sym = LlmSymbol {
-                text: text.to_string(),
-                n: std::cmp::min(len, text.len() - offs),
+                text: text[offs..offs + n].to_string(),
+                n,
                 prev: index - 1,
                 next: if offs + len >= text.len() {
                     -1
@@ -395,6 +599,8 @@ impl<'a> LlmTokenizerSpmSession {
             }
 
             // 将右符号合并到左符号中
+            let right_text = std::mem::take(&mut self.symbols[right_idx].text);
+            self.symbols[left_idx].text.push_str(&right_text);
             self.symbols[left_idx].n += right_sym_n;
             self.symbols[right_idx].n = 0;
 
@@ -412,7 +618,7 @@ impl<'a> LlmTokenizerSpmSession {
         }
 
         // 处理最终的符号
-        let mut i = 0;
+        let mut i = if self.symbols.is_empty() { -1 } else { 0 };
         while i != -1 {
             let symbol = &self.symbols[i as usize];
             self.resegment(symbol, output, config);
@@ -486,12 +692,9 @@ impl<'a> LlmTokenizerSpmSession {
             return;
         }
 
-        // 如果没有找到映射，将每个字节作为单独的标记输出
-        for i in 0..symbol.n {
-            if let Some(byte) = symbol.text.as_bytes().get(i) {
-                let id = config.byte_to_token(*byte);
-                output.push(id);
-            }
+        // 如果没有找到映射，将每个字节作为单独的标记输出，词表中没有的字节输出 unk
+        for &byte in &symbol.text.as_bytes()[..symbol.n] {
+            output.push(config.try_byte_to_token(byte).unwrap_or(config.unk()))
         }
     }
 }
@@ -0,0 +1,153 @@
+//! 增量解码，逐个标记产生文本。
+
+use super::{Gpt2Tokenizer, common::TokenId};
+
+/// 增量解码器，由 [`Gpt2Tokenizer::decode_stream`] 创建。
+///
+/// 不完整的 utf-8 序列会保留到后续的标记补齐；设置了 `clean_spaces` 时，
+/// 空格会保留到能确定是否需要删除为止。全部输出拼接后与 [`Gpt2Tokenizer::decode_sequence`] 相同。
+pub struct DecodeStream<'a> {
+    tokenizer: &'a Gpt2Tokenizer,
+    /// 尚未凑成完整字符的字节
+    bytes: Vec<u8>,
+    /// 已解码但由于清理空格需要向后查看而暂未输出的字符
+    held: Vec<char>,
+    /// 是否已经输出过字符
+    emitted: bool,
+}
+
+impl Gpt2Tokenizer {
+    /// 创建一个增量解码器
+    pub fn decode_stream(&self) -> DecodeStream<'_> {
+        DecodeStream {
+            tokenizer: self,
+            bytes: Vec::new(),
+            held: Vec::new(),
+            emitted: false,
+        }
+    }
+}
+
+impl DecodeStream<'_> {
+    /// 解码一个标记，返回新确定的文本
+    pub fn push(&mut self, token: TokenId) -> String {
+        self.tokenizer.decode_piece_into(token, &mut self.bytes);
+        self.decode_bytes();
+        self.drain(false)
+    }
+
+    /// 结束解码，返回剩余的文本，不完整的 utf-8 序列替换为 `U+FFFD`
+    pub fn finish(mut self) -> String {
+        if !self.bytes.is_empty() {
+            self.held.push(char::REPLACEMENT_CHARACTER);
+            self.bytes.clear()
+        }
+        self.drain(true)
+    }
+
+    /// 将完整的字符移入 `held`，与 [`String::from_utf8_lossy`] 一样替换非法序列
+    fn decode_bytes(&mut self) {
+        let mut rest = &self.bytes[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(s) => {
+                    self.held.extend(s.chars());
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, tail) = rest.split_at(e.valid_up_to());
+                    self.held
+                        .extend(unsafe { std::str::from_utf8_unchecked(valid) }.chars());
+                    match e.error_len() {
+                        Some(len) => {
+                            self.held.push(char::REPLACEMENT_CHARACTER);
+                            rest = &tail[len..]
+                        }
+                        // 末尾的不完整序列等待后续字节
+                        None => {
+                            rest = tail;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        let consumed = self.bytes.len() - rest.len();
+        self.bytes.drain(..consumed);
+    }
+
+    /// 输出能够确定的字符，`end` 表示后面不会再有字符
+    fn drain(&mut self, end: bool) -> String {
+        let mut ans = String::new();
+        let mut i = 0;
+        while i < self.held.len() {
+            let c = self.held[i];
+            if c == ' ' && self.tokenizer.config.flags.clean_spaces {
+                let skip = match (&self.held[i + 1..], end) {
+                    ([' ', ..], _) => Some(true),
+                    (['?' | '!' | '.' | ',', ..], _) => Some(self.emitted),
+                    (['\'', 's' | 'm', ..], _) | (['\'', 'r' | 'v', 'e', ..], _) => {
+                        Some(self.emitted)
+                    }
+                    ([] | ['\''] | ['\'', 'r' | 'v'], false) => None,
+                    _ => Some(false),
+                };
+                match skip {
+                    // 需要更多的字符才能确定
+                    None => break,
+                    Some(true) => {
+                        i += 1;
+                        continue;
+                    }
+                    Some(false) => {}
+                }
+            }
+            ans.push(c);
+            self.emitted = true;
+            i += 1
+        }
+        self.held.drain(..i);
+        ans
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::gpt2::{VocabType, gpt2_tests::spm_tokenizer, unicode::unicode_byte_encoding};
+
+    #[test]
+    fn test_decode_stream() {
+        let pieces = [
+            "Hello", " ", " ,", " world", " '", "s", " '", "r", "e", " .", " 中", "文", " ",
+        ]
+        .map(unicode_byte_encoding);
+        // "中" 的 utf-8 字节拆分到两个标记中
+        let mut pieces = pieces.to_vec();
+        let zh = pieces.remove(10);
+        let (a, b) = zh.split_at(zh.char_indices().nth(2).unwrap().0);
+        pieces.insert(10, a.into());
+        pieces.insert(11, b.into());
+
+        let mut tokenizer =
+            spm_tokenizer(&pieces.iter().map(|p| (p.as_str(), 0.)).collect::<Vec<_>>());
+        tokenizer.vocab_type = VocabType::Bpe;
+        let tokens = (0..pieces.len() as TokenId).collect::<Vec<_>>();
+        for clean_spaces in [true, false] {
+            tokenizer.config.flags.clean_spaces = clean_spaces;
+            let mut stream = tokenizer.decode_stream();
+            let mut text = String::new();
+            for &token in &tokens {
+                text += &stream.push(token)
+            }
+            text += &stream.finish();
+            assert_eq!(text, tokenizer.decode_sequence(&tokens));
+        }
+
+        // 不完整的序列在结束时替换
+        let mut stream = tokenizer.decode_stream();
+        assert_eq!(stream.push(10), " ");
+        assert_eq!(stream.finish(), "\u{FFFD}");
+    }
+}
@@ -1,7 +1,81 @@
-use std::collections::HashMap;
+use super::{
+    common::{GPT2, LLAMA3, LLAMA3_CASE_SENSITIVE, QWEN},
+    unicode_data::NFD_DECOMPOSABLE,
+};
+use std::{collections::HashMap, ops::Range, sync::LazyLock};
 
 /// 将文本按照正则表达式分割成多个部分
+#[allow(unused)]
 pub fn unicode_regex_split(text: &str, regex_exprs: &[String]) -> Vec<String> {
+    unicode_regex_split_ranges(text, regex_exprs)
+        .into_iter()
+        .map(|range| unicode_byte_encoding(&text[range]))
+        .collect()
+}
+
+/// 将文本按照正则表达式分割成多个部分，返回每个部分在原文中的字节范围
+///
+/// 与 [`unicode_regex_split`] 不同，这个函数不做字节编码，也不为每个部分分配字符串。
+/// 多个正则表达式依次应用，后一个表达式在前一个分割出的每个部分内继续分割。
+pub fn unicode_regex_split_ranges(text: &str, regex_exprs: &[String]) -> Vec<Range<usize>> {
+    let Some((first, rest)) = regex_exprs.split_first() else {
+        return if text.is_empty() {
+            vec![]
+        } else {
+            std::iter::once(0..text.len()).collect()
+        };
+    };
+    let mut ranges = unicode_regex_split_one(text, first);
+    for regex_expr in rest {
+        ranges = ranges
+            .into_iter()
+            .flat_map(|range| {
+                let start = range.start;
+                unicode_regex_split_one(&text[range], regex_expr)
+                    .into_iter()
+                    .map(move |r| r.start + start..r.end + start)
+            })
+            .collect()
+    }
+    ranges
+}
+
+/// 逐个产生分割出的部分，结果与 [`unicode_regex_split_ranges`] 对应的原文切片相同。
+///
+/// 第一个正则表达式一次分割全文，其余的表达式在取出每个部分时才应用，
+/// 不保存多个表达式分割的完整中间结果，也不为每个部分分配字符串。
+pub fn unicode_regex_split_iter<'a>(
+    text: &'a str,
+    regex_exprs: &'a [String],
+) -> impl Iterator<Item = &'a str> + 'a {
+    let (first, rest) = regex_exprs
+        .split_first()
+        .map_or((&[][..], &[][..]), |(first, rest)| {
+            (std::slice::from_ref(first), rest)
+        });
+    unicode_regex_split_ranges(text, first)
+        .into_iter()
+        .flat_map(move |range| {
+            let piece = &text[range];
+            unicode_regex_split_ranges(piece, rest)
+                .into_iter()
+                .map(move |r| &piece[r])
+        })
+}
+
+/// 使用一个正则表达式分割文本
+fn unicode_regex_split_one(text: &str, regex_expr: &str) -> Vec<Range<usize>> {
+    // 已知的正则表达式使用手写的分割实现，避免 fancy_regex 的开销。
+    // 手写实现以码点为单位工作，输入和输出的长度都是码点数，在此统一转换为字节范围
+    let offsets = [text.chars().count()];
+    match unicode_regex_split_custom(text, regex_expr, &offsets) {
+        Some(bpe_offsets) => cpt_offsets_to_ranges(text, &bpe_offsets),
+        None => unicode_regex_split_fancy(text, regex_expr),
+    }
+}
+
+/// 使用 fancy_regex 分割文本
+fn unicode_regex_split_fancy(text: &str, regex_expr: &str) -> Vec<Range<usize>> {
     // Unicode 类别
     let k_ucat_enum: HashMap<&str, u32> = [
         ("\\p{N}", unicode_cpt_flags::NUMBER),
@@ -39,14 +113,7 @@ pub fn unicode_regex_split(text: &str, regex_exprs: &[String]) -> Vec<String> {
     .cloned()
     .collect();
 
-    let tmp = process_regex(
-        &regex_exprs[0],
-        text,
-        &k_ucat_enum,
-        &k_ucat_cpt,
-        &k_ucat_map,
-    );
-    unicode_byte_encoding_process(&tmp)
+    process_regex(regex_expr, text, &k_ucat_enum, &k_ucat_cpt, &k_ucat_map)
 }
 
 /// 处理正则表达式
@@ -56,36 +123,38 @@ fn process_regex(
     k_ucat_enum: &HashMap<&str, u32>,
     k_ucat_cpt: &HashMap<u32, u8>,
     k_ucat_map: &HashMap<u32, &str>,
-) -> Vec<String> {
+) -> Vec<Range<usize>> {
     // 生成正则表达式的折叠表示
     let mut regex_expr_collapsed = String::new();
 
+    // 按码点索引，表达式中可能有非 ascii 字符
+    let chars = regex_expr.chars().collect::<Vec<_>>();
     let mut inside = false;
     let mut i = 0;
-    while i < regex_expr.len() {
-        let c = regex_expr.chars().nth(i).unwrap();
+    while i < chars.len() {
+        let c = chars[i];
 
-        if c == '[' && (i == 0 || regex_expr.chars().nth(i - 1).unwrap() != '\\') {
+        if c == '[' && (i == 0 || chars[i - 1] != '\\') {
             regex_expr_collapsed.push('[');
             inside = true;
             i += 1;
             continue;
         }
 
-        if inside && c == ']' && regex_expr.chars().nth(i - 1).unwrap() != '\\' {
+        if inside && c == ']' && chars[i - 1] != '\\' {
             regex_expr_collapsed.push(']');
             inside = false;
             i += 1;
             continue;
         }
 
-        if i + 4 < regex_expr.len()
-            && regex_expr.chars().nth(i).unwrap() == '\\'
-            && regex_expr.chars().nth(i + 1).unwrap() == 'p'
-            && regex_expr.chars().nth(i + 2).unwrap() == '{'
-            && regex_expr.chars().nth(i + 4).unwrap() == '}'
+        if i + 4 < chars.len()
+            && chars[i] == '\\'
+            && chars[i + 1] == 'p'
+            && chars[i + 2] == '{'
+            && chars[i + 4] == '}'
         {
-            let pat = format!("\\p{{{}}}", regex_expr.chars().nth(i + 3).unwrap());
+            let pat = format!("\\p{{{}}}", chars[i + 3]);
             if let Some(&cat_flag) = k_ucat_enum.get(pat.as_str()) {
                 if !inside {
                     regex_expr_collapsed.push('[');
@@ -125,10 +194,10 @@ fn process_regex(
                     if let Some(m) = cap.get(0) {
                         // 如果匹配前有未匹配的文本，添加到结果中
                         if m.start() > last_end {
-                            result.push(text[last_end..m.start()].to_string());
+                            result.push(last_end..m.start());
                         }
                         // 添加匹配的文本
-                        result.push(text[m.start()..m.end()].to_string());
+                        result.push(m.range());
                         last_end = m.end();
                     }
                 }
@@ -136,7 +205,7 @@ fn process_regex(
 
             // 添加最后一部分未匹配的文本
             if last_end < text.len() {
-                result.push(text[last_end..].to_string());
+                result.push(last_end..text.len());
             }
 
             result
@@ -145,25 +214,131 @@ fn process_regex(
     }
 }
 
-/// 自定义正则表达式分割实现
-fn unicode_regex_split_custom(text: &str, regex_expr: &str, offsets: &[usize]) -> Vec<usize> {
-    if regex_expr == "'s|'t|'re|'ve|'m|'ll|'d| ?\\p{L}+| ?\\p{N}+| ?[^\\s\\p{L}\\p{N}]+|\\s+(?!\\S)"
-    {
-        unicode_regex_split_custom_gpt2(text, offsets)
-    } else if regex_expr
-        == "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+"
-        || regex_expr
-            == "(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+"
-    {
-        unicode_regex_split_custom_llama3(text, offsets)
+/// 合并落在扩展字素簇内部的相邻分割，使每个字素簇完整地属于一个部分
+pub fn unicode_merge_graphemes(text: &str, ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut ans: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match ans.last_mut() {
+            Some(last) if !is_grapheme_boundary(text, range.start) => last.end = range.end,
+            _ => ans.push(range),
+        }
+    }
+    ans
+}
+
+/// 判断 `pos` 处是否是扩展字素簇的边界，是 UAX #29 规则的简化实现
+fn is_grapheme_boundary(text: &str, pos: usize) -> bool {
+    let (Some(prev), Some(next)) = (text[..pos].chars().next_back(), text[pos..].chars().next())
+    else {
+        return true;
+    };
+    const ZWJ: char = '\u{200d}';
+    match (prev, next) {
+        // GB3
+        ('\r', '\n') => false,
+        // GB4, GB5
+        ('\r' | '\n', _) | (_, '\r' | '\n') => true,
+        // GB9
+        (_, c) if c == ZWJ || is_grapheme_extend(c) => false,
+        // GB11
+        (ZWJ, c) if is_extended_pictographic(c) => {
+            let mut before = text[..pos].chars().rev().skip(1);
+            !before
+                .find(|&c| !is_grapheme_extend(c))
+                .is_some_and(is_extended_pictographic)
+        }
+        // GB12, GB13
+        (p, n) if is_regional_indicator(p) && is_regional_indicator(n) => {
+            let count = text[..pos]
+                .chars()
+                .rev()
+                .take_while(|&c| is_regional_indicator(c))
+                .count();
+            count % 2 == 0
+        }
+        _ => true,
+    }
+}
+
+/// 不会成为字素簇开头的字符：组合符号、变体选择符、emoji 修饰符和标签符号
+fn is_grapheme_extend(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036f
+        | 0x0483..=0x0489
+        | 0x0591..=0x05bd
+        | 0x0610..=0x061a
+        | 0x064b..=0x065f
+        | 0x0e31 | 0x0e34..=0x0e3a | 0x0e47..=0x0e4e
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200c
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f
+        | 0x1f3fb..=0x1f3ff
+        | 0xe0020..=0xe007f
+        | 0xe0100..=0xe01ef
+    )
+}
+
+fn is_extended_pictographic(c: char) -> bool {
+    matches!(c as u32,
+        0x00a9 | 0x00ae | 0x203c | 0x2049 | 0x2122 | 0x2139
+        | 0x2194..=0x21aa
+        | 0x2300..=0x23ff
+        | 0x2600..=0x27bf
+        | 0x2b00..=0x2bff
+        | 0x3030 | 0x303d | 0x3297 | 0x3299
+        | 0x1f000..=0x1f1e5
+        | 0x1f200..=0x1faff
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1f1e6..=0x1f1ff)
+}
+
+/// 将以码点数表示的分割长度转换为原文中的字节范围
+fn cpt_offsets_to_ranges(text: &str, offsets: &[usize]) -> Vec<Range<usize>> {
+    let mut chars = text.char_indices().map(|(i, _)| i).chain([text.len()]);
+    let mut start = chars.next().unwrap();
+    let ans = offsets
+        .iter()
+        .map(|&len| {
+            let end = chars.nth(len - 1).unwrap();
+            let range = start..end;
+            start = end;
+            range
+        })
+        .collect::<Vec<_>>();
+    debug_assert_eq!(start, text.len(), "offsets do not cover the text");
+    ans
+}
+
+/// 自定义正则表达式分割实现，不是已知的正则表达式时返回 `None`。
+///
+/// `offsets` 和返回值中的长度都以码点数为单位，而不是字节数。
+fn unicode_regex_split_custom(
+    text: &str,
+    regex_expr: &str,
+    offsets: &[usize],
+) -> Option<Vec<usize>> {
+    if regex_expr == GPT2 {
+        Some(unicode_regex_split_custom_gpt2(text, offsets))
+    } else if regex_expr == LLAMA3 || regex_expr == LLAMA3_CASE_SENSITIVE {
+        Some(unicode_regex_split_custom_llama3(text, offsets))
+    } else if regex_expr == QWEN && text.is_ascii() {
+        // 码点分类只覆盖 ascii，其他文本仍交给正则表达式，保证 \p{L} 等类别正确
+        Some(unicode_regex_split_custom_qwen(text, offsets))
     } else {
-        Vec::new()
+        None
     }
 }
 
 /// GPT2 系统正则表达式分割实现
 fn unicode_regex_split_custom_gpt2(text: &str, offsets: &[usize]) -> Vec<usize> {
     let cpts = unicode_cpts_from_utf8(text);
+    let flags = unicode_cpt_flags_from_cpts(&cpts);
     let mut bpe_offsets = Vec::with_capacity(offsets.len());
 
     let mut start = 0;
@@ -185,7 +360,7 @@ fn unicode_regex_split_custom_gpt2(text: &str, offsets: &[usize]) -> Vec<usize>
 
         let get_flags = |pos: usize| -> unicode_cpt_flags {
             if offset_ini <= pos && pos < offset_end {
-                unicode_cpt_flags_from_cpt(cpts[pos])
+                flags[pos]
             } else {
                 unicode_cpt_flags::default()
             }
@@ -259,7 +434,9 @@ fn unicode_regex_split_custom_gpt2(text: &str, offsets: &[usize]) -> Vec<usize>
             }
 
             // 正则表达式: <space>?[^\s\p{L}\p{N}]+
-            if !(flags2.is_whitespace | flags2.is_letter | flags2.is_number) && flags.as_uint() != 0
+            // 空格后至少要有一个字符，文本末尾的单个空格由下面的 \s+ 匹配
+            if !(flags2.is_whitespace | flags2.is_letter | flags2.is_number)
+                && flags2.as_uint() != 0
             {
                 pos += (cpt == ' ' as u32) as usize;
                 while !(get_flags(pos).is_whitespace
@@ -303,7 +480,22 @@ fn unicode_regex_split_custom_gpt2(text: &str, offsets: &[usize]) -> Vec<usize>
 
 /// LLAMA3 系统正则表达式分割实现
 fn unicode_regex_split_custom_llama3(text: &str, offsets: &[usize]) -> Vec<usize> {
+    unicode_regex_split_custom_llama3_like(text, offsets, 3)
+}
+
+/// QWEN 正则表达式分割实现，与 LLAMA3 只在数字的切分上不同：每个数字单独成为一个片段
+fn unicode_regex_split_custom_qwen(text: &str, offsets: &[usize]) -> Vec<usize> {
+    unicode_regex_split_custom_llama3_like(text, offsets, 1)
+}
+
+/// LLAMA3 风格的分割实现，连续的数字每 `max_digits` 个切分
+fn unicode_regex_split_custom_llama3_like(
+    text: &str,
+    offsets: &[usize],
+    max_digits: usize,
+) -> Vec<usize> {
     let cpts = unicode_cpts_from_utf8(text);
+    let flags = unicode_cpt_flags_from_cpts(&cpts);
     let mut bpe_offsets = Vec::with_capacity(offsets.len());
 
     let mut start = 0;
@@ -325,7 +517,7 @@ fn unicode_regex_split_custom_llama3(text: &str, offsets: &[usize]) -> Vec<usize
 
         let get_flags = |pos: usize| -> unicode_cpt_flags {
             if offset_ini <= pos && pos < offset_end {
-                unicode_cpt_flags_from_cpt(cpts[pos])
+                flags[pos]
             } else {
                 unicode_cpt_flags::default()
             }
@@ -385,11 +577,11 @@ fn unicode_regex_split_custom_llama3(text: &str, offsets: &[usize]) -> Vec<usize
                 }
             }
 
-            // 正则表达式: \p{N}{1,3}
+            // 正则表达式: \p{N}{1,3}，QWEN 为 \p{N}
             if flags.is_number {
                 let mut ini = pos;
                 while get_flags(pos).is_number {
-                    if pos - ini >= 3 {
+                    if pos - ini >= max_digits {
                         add_token(pos);
                         ini = pos;
                     }
@@ -465,7 +657,7 @@ fn unicode_regex_split_custom_llama3(text: &str, offsets: &[usize]) -> Vec<usize
 }
 
 /// Unicode 代码点标志结构体
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct unicode_cpt_flags {
     pub is_whitespace: bool,
     pub is_letter: bool,
@@ -475,6 +667,7 @@ pub struct unicode_cpt_flags {
     pub is_accent_mark: bool,
     pub is_lowercase: bool,
     pub is_uppercase: bool,
+    /// 码点经 NFD 规范化后保持不变，组合附加符号是，可分解的预组合字符不是
     pub is_nfd: bool,
 }
 
@@ -608,10 +801,35 @@ fn unicode_cpt_flags_from_cpt(cpt: u32) -> unicode_cpt_flags {
     {
         flags.is_punctuation = true;
     }
+    flags.is_nfd = unicode_cpt_is_nfd(cpt);
 
     flags
 }
 
+/// 判断码点经 NFD 规范化后是否保持不变
+fn unicode_cpt_is_nfd(cpt: u32) -> bool {
+    // 最小的可分解码点是 U+00C0
+    cpt < NFD_DECOMPOSABLE[0].0
+        || NFD_DECOMPOSABLE
+            .binary_search_by(|&(first, last)| {
+                if last < cpt {
+                    std::cmp::Ordering::Less
+                } else if first > cpt {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_err()
+}
+
+/// 一次性计算所有代码点的标志，切分时按位置索引，避免重复分类
+fn unicode_cpt_flags_from_cpts(cpts: &[u32]) -> Vec<unicode_cpt_flags> {
+    cpts.iter()
+        .map(|&cpt| unicode_cpt_flags_from_cpt(cpt))
+        .collect()
+}
+
 fn unicode_tolower(cpt: u32) -> u32 {
     // 简单的小写转换实现
     if cpt >= 'A' as u32 && cpt <= 'Z' as u32 {
@@ -621,9 +839,11 @@ fn unicode_tolower(cpt: u32) -> u32 {
 }
 
 pub fn unicode_byte_to_utf8(ch: u8) -> String {
-    let map = unicode_byte_to_utf8_map();
-    map.get(&ch).unwrap().to_string()
+    BYTE_TO_UTF8[&ch].to_string()
 }
+
+static BYTE_TO_UTF8: LazyLock<HashMap<u8, char>> = LazyLock::new(unicode_byte_to_utf8_map);
+
 /// 创建一个从字节到 UTF-8 字符串的映射
 pub fn unicode_byte_to_utf8_map() -> HashMap<u8, char> {
     let mut map = HashMap::new();
@@ -654,15 +874,24 @@ pub fn unicode_byte_to_utf8_map() -> HashMap<u8, char> {
 
     map
 }
-fn unicode_byte_encoding_process(bpe_words: &[String]) -> Vec<String> {
-    bpe_words
-        .into_iter()
-        .map(|word: &String| {
-            word.chars()
-                .map(|ch| unicode_byte_to_utf8(ch as u8))
-                .collect()
-        })
-        .collect()
+/// 对一个预分词片段做字节编码，将每个字节映射为对应的可见字符
+pub fn unicode_byte_encoding(piece: &str) -> String {
+    piece.bytes().map(|b| BYTE_TO_UTF8[&b]).collect()
+}
+
+static UTF8_TO_BYTE: LazyLock<HashMap<char, u8>> =
+    LazyLock::new(|| BYTE_TO_UTF8.iter().map(|(&b, &c)| (c, b)).collect());
+
+/// [`unicode_byte_encoding`] 的逆过程，将可见字符还原为字节，不在映射中的字符保持原样
+pub fn unicode_byte_decoding(piece: &str) -> Vec<u8> {
+    let mut ans = Vec::with_capacity(piece.len());
+    for c in piece.chars() {
+        match UTF8_TO_BYTE.get(&c) {
+            Some(&b) => ans.push(b),
+            None => ans.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    ans
 }
 
 /// 获取 UTF-8 字符的长度
@@ -679,3 +908,250 @@ pub fn unicode_len_utf8(byte: u8) -> usize {
         1 // 无效的 UTF-8 序列，返回 1
     }
 }
+
+#[cfg(test)]
+mod unicode_tests {
+    use super::*;
+    use crate::gpt2::common::DEEPSEEK_LLM;
+
+    #[test]
+    fn test_split_ranges_reconstruct_pieces() {
+        let regex_exprs = [QWEN.to_string()];
+        let text = "Hello, world! It's 2025.\n  Let's   tokenize\tthis.";
+
+        let ranges = unicode_regex_split_ranges(text, &regex_exprs);
+        // 范围首尾相接且覆盖全文
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, text.len());
+        assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+
+        let pieces = ranges
+            .into_iter()
+            .map(|range| unicode_byte_encoding(&text[range]))
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, unicode_regex_split(text, &regex_exprs))
+    }
+
+    const CORPUS: &[&str] = &[
+        "Hello world",
+        "I'm sure it's fine, they'll say we've done what we'd REALLY planned.",
+        "Numbers: 1 12 123 1234 12345, and 3.14159!",
+        "  leading spaces and trailing spaces  x",
+        "tabs\tand\nnew\r\nlines\n\n  \n end",
+        "symbols #$%&*()[]{}<>=+-_/\\|~`^ mixed",
+        "email@example.com, https://example.com/path?q=1&r=2",
+        "fn main() {\n    println!(\"{}\", 42);\n}",
+    ];
+
+    #[test]
+    fn test_custom_split_matches_regex() {
+        for regex_expr in [GPT2, LLAMA3, LLAMA3_CASE_SENSITIVE] {
+            for text in CORPUS {
+                let offsets = [text.chars().count()];
+                let custom = unicode_regex_split_custom(text, regex_expr, &offsets).unwrap();
+                assert_eq!(
+                    cpt_offsets_to_ranges(text, &custom),
+                    unicode_regex_split_fancy(text, regex_expr),
+                    "{regex_expr:?} splits {text:?} differently"
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn test_gpt2_trailing_whitespace() {
+        for (text, expected) in [
+            ("a ", &["a", " "][..]),
+            ("a  ", &["a", "  "]),
+            ("a   ", &["a", "   "]),
+            ("a   b", &["a", "  ", " b"]),
+            ("a , ", &["a", " ,", " "]),
+        ] {
+            let custom = unicode_regex_split_custom_gpt2(text, &[text.chars().count()]);
+            let ranges = cpt_offsets_to_ranges(text, &custom);
+            assert_eq!(ranges, unicode_regex_split_fancy(text, GPT2), "{text:?}");
+            let pieces = ranges.into_iter().map(|r| &text[r]).collect::<Vec<_>>();
+            assert_eq!(pieces, expected)
+        }
+    }
+
+    #[test]
+    fn test_unknown_regex_falls_back() {
+        let text = "Hello 12345";
+        let expr = "\\p{L}+|\\p{N}{1,2}|\\s+";
+        assert!(unicode_regex_split_custom(text, expr, &[text.chars().count()]).is_none());
+        assert_eq!(
+            unicode_regex_split_ranges(text, &[expr.to_string()]),
+            unicode_regex_split_fancy(text, expr)
+        )
+    }
+
+    #[test]
+    fn test_custom_split_non_ascii_boundaries() {
+        let text = "hi 😀 there 👍🏽! 中文 a😀b";
+        for regex_expr in [GPT2, LLAMA3, LLAMA3_CASE_SENSITIVE] {
+            let ranges = unicode_regex_split_ranges(text, &[regex_expr.to_string()]);
+            assert!(
+                ranges
+                    .iter()
+                    .all(|r| text.is_char_boundary(r.start) && text.is_char_boundary(r.end))
+            );
+            assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+            assert_eq!(ranges.last().unwrap().end, text.len());
+            let pieces = ranges.into_iter().map(|r| &text[r]).collect::<Vec<_>>();
+            assert_eq!(pieces.concat(), text);
+            assert!(pieces.contains(&"😀"), "{regex_expr:?} splits {pieces:?}")
+        }
+    }
+
+    #[test]
+    fn test_byte_decoding_round_trip() {
+        let text = "Hello world, 你好 😀\n\t";
+        let encoded = unicode_byte_encoding(text);
+        assert!(!encoded.contains(' '));
+        assert_eq!(unicode_byte_decoding(&encoded), text.as_bytes());
+        assert_eq!(unicode_byte_decoding("<|im_end|>"), b"<|im_end|>");
+    }
+
+    #[test]
+    fn test_merge_graphemes() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let flag = "🇨🇳";
+        let text = format!("hi {family}! cafe\u{301} {flag}🇯🇵\r\n");
+        let split = |graphemes: bool| {
+            let ranges = unicode_regex_split_ranges(&text, &[GPT2.to_string()]);
+            let ranges = if graphemes {
+                unicode_merge_graphemes(&text, ranges)
+            } else {
+                ranges
+            };
+            ranges.into_iter().map(|r| &text[r]).collect::<Vec<_>>()
+        };
+
+        let pieces = split(false);
+        assert!(!pieces.contains(&family));
+        assert_eq!(pieces.concat(), text);
+
+        let pieces = split(true);
+        assert_eq!(pieces.concat(), text);
+        assert!(pieces.contains(&family), "{pieces:?}");
+        assert!(pieces.contains(&flag), "{pieces:?}");
+        assert!(
+            pieces.iter().any(|p| p.ends_with("cafe\u{301}")),
+            "{pieces:?}"
+        );
+    }
+
+    #[test]
+    fn test_multi_regex_split() {
+        let text = "ab12 3c";
+        let exprs = ["[^ ]+| +".to_string(), "\\d+".to_string()];
+        let pieces = unicode_regex_split_ranges(text, &exprs)
+            .into_iter()
+            .map(|r| &text[r])
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, ["ab", "12", " ", "3", "c"]);
+        // 只有第一个表达式时不再细分
+        let pieces = unicode_regex_split_ranges(text, &exprs[..1])
+            .into_iter()
+            .map(|r| &text[r])
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, ["ab12", " ", "3c"]);
+
+        let text = "Hello 世界123\n";
+        let exprs = DEEPSEEK_LLM
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        let pieces = unicode_regex_split_ranges(text, &exprs)
+            .into_iter()
+            .map(|r| &text[r])
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, ["Hello", " ", "世界", "123", "\n"]);
+    }
+
+    #[test]
+    fn test_cpt_flags_batch() {
+        let text = "Hello, World! 123 中文\t\r\n  I'm fine?? ~`{}|😀 ".repeat(200);
+        let cpts = unicode_cpts_from_utf8(&text);
+        let flags = unicode_cpt_flags_from_cpts(&cpts);
+        assert_eq!(flags.len(), cpts.len());
+        for (&cpt, &flag) in cpts.iter().zip(&flags) {
+            assert_eq!(flag, unicode_cpt_flags_from_cpt(cpt))
+        }
+        // ascii 文本的切分结果与正则表达式一致
+        let text = "Hello, World! 123 \t\r\n  I'm fine?? ~`{}| ".repeat(200);
+        let exprs = [GPT2.to_string()];
+        assert_eq!(
+            unicode_regex_split_ranges(&text, &exprs),
+            unicode_regex_split_fancy(&text, GPT2)
+        );
+    }
+
+    #[test]
+    fn test_custom_qwen() {
+        let exprs = [QWEN.to_string()];
+        assert_eq!(
+            unicode_regex_split("12345", &exprs),
+            ["1", "2", "3", "4", "5"]
+        );
+        // ascii 文本由手写实现切分，结果与正则表达式一致
+        let text = "I'm 2024 years\r\n  old, isn't it?? 1+1=2   ";
+        assert!(unicode_regex_split_custom(text, QWEN, &[text.chars().count()]).is_some());
+        assert_eq!(
+            unicode_regex_split_ranges(text, &exprs),
+            unicode_regex_split_fancy(text, QWEN)
+        );
+        // 非 ascii 文本仍使用正则表达式
+        assert!(unicode_regex_split_custom("中文12", QWEN, &[4]).is_none());
+        assert_eq!(
+            unicode_regex_split_ranges("中文12", &exprs),
+            [0..6, 6..7, 7..8]
+        );
+    }
+
+    #[test]
+    fn test_split_iter() {
+        let deepseek = DEEPSEEK_LLM
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        for exprs in [vec![], vec![GPT2.to_string()], deepseek] {
+            for text in CORPUS {
+                let pieces = unicode_regex_split_iter(text, &exprs).collect::<Vec<_>>();
+                let ranges = unicode_regex_split_ranges(text, &exprs);
+                assert_eq!(
+                    pieces,
+                    ranges.into_iter().map(|r| &text[r]).collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    pieces
+                        .into_iter()
+                        .map(unicode_byte_encoding)
+                        .collect::<Vec<_>>(),
+                    unicode_regex_split(text, &exprs)
+                )
+            }
+        }
+        assert_eq!(unicode_regex_split_iter("", &[GPT2.to_string()]).count(), 0);
+    }
+
+    #[test]
+    fn test_cpt_is_nfd() {
+        let is_nfd = |c: char| unicode_cpt_flags_from_cpt(c as u32).is_nfd;
+        // 组合附加符号和不可分解的字符
+        for c in [
+            '\u{0300}', '\u{0301}', '\u{0308}', '\u{036F}', 'a', 'Z', ' ', '中', '😀',
+        ] {
+            assert!(is_nfd(c), "{c:?}")
+        }
+        // 可分解的预组合字符：é = e + U+0301，Å = A + U+030A，韩文音节
+        for c in ['é', 'Å', 'ǖ', 'ệ', '한', '\u{AC00}', '\u{D7A3}'] {
+            assert!(!is_nfd(c), "{c:?}")
+        }
+        // 区间首尾相接处
+        assert!(!is_nfd('\u{00C0}'));
+        assert!(is_nfd('\u{00BF}'));
+        assert!(NFD_DECOMPOSABLE.windows(2).all(|w| w[0].1 < w[1].0));
+    }
+}
@@ -0,0 +1,239 @@
+//! 由 Unicode 数据生成的码点表。
+
+/// NFD 规范化后会改变的码点（可分解的预组合字符和韩文音节）的闭区间，按起点升序排列。
+///
+/// 由 Unicode 14.0.0 的数据生成：逐个码点比较 NFD 规范化前后是否相同。
+pub(super) const NFD_DECOMPOSABLE: &[(u32, u32)] = &[
+    (0x000C0, 0x000C5),
+    (0x000C7, 0x000CF),
+    (0x000D1, 0x000D6),
+    (0x000D9, 0x000DD),
+    (0x000E0, 0x000E5),
+    (0x000E7, 0x000EF),
+    (0x000F1, 0x000F6),
+    (0x000F9, 0x000FD),
+    (0x000FF, 0x0010F),
+    (0x00112, 0x00125),
+    (0x00128, 0x00130),
+    (0x00134, 0x00137),
+    (0x00139, 0x0013E),
+    (0x00143, 0x00148),
+    (0x0014C, 0x00151),
+    (0x00154, 0x00165),
+    (0x00168, 0x0017E),
+    (0x001A0, 0x001A1),
+    (0x001AF, 0x001B0),
+    (0x001CD, 0x001DC),
+    (0x001DE, 0x001E3),
+    (0x001E6, 0x001F0),
+    (0x001F4, 0x001F5),
+    (0x001F8, 0x0021B),
+    (0x0021E, 0x0021F),
+    (0x00226, 0x00233),
+    (0x00340, 0x00341),
+    (0x00343, 0x00344),
+    (0x00374, 0x00374),
+    (0x0037E, 0x0037E),
+    (0x00385, 0x0038A),
+    (0x0038C, 0x0038C),
+    (0x0038E, 0x00390),
+    (0x003AA, 0x003B0),
+    (0x003CA, 0x003CE),
+    (0x003D3, 0x003D4),
+    (0x00400, 0x00401),
+    (0x00403, 0x00403),
+    (0x00407, 0x00407),
+    (0x0040C, 0x0040E),
+    (0x00419, 0x00419),
+    (0x00439, 0x00439),
+    (0x00450, 0x00451),
+    (0x00453, 0x00453),
+    (0x00457, 0x00457),
+    (0x0045C, 0x0045E),
+    (0x00476, 0x00477),
+    (0x004C1, 0x004C2),
+    (0x004D0, 0x004D3),
+    (0x004D6, 0x004D7),
+    (0x004DA, 0x004DF),
+    (0x004E2, 0x004E7),
+    (0x004EA, 0x004F5),
+    (0x004F8, 0x004F9),
+    (0x00622, 0x00626),
+    (0x006C0, 0x006C0),
+    (0x006C2, 0x006C2),
+    (0x006D3, 0x006D3),
+    (0x00929, 0x00929),
+    (0x00931, 0x00931),
+    (0x00934, 0x00934),
+    (0x00958, 0x0095F),
+    (0x009CB, 0x009CC),
+    (0x009DC, 0x009DD),
+    (0x009DF, 0x009DF),
+    (0x00A33, 0x00A33),
+    (0x00A36, 0x00A36),
+    (0x00A59, 0x00A5B),
+    (0x00A5E, 0x00A5E),
+    (0x00B48, 0x00B48),
+    (0x00B4B, 0x00B4C),
+    (0x00B5C, 0x00B5D),
+    (0x00B94, 0x00B94),
+    (0x00BCA, 0x00BCC),
+    (0x00C48, 0x00C48),
+    (0x00CC0, 0x00CC0),
+    (0x00CC7, 0x00CC8),
+    (0x00CCA, 0x00CCB),
+    (0x00D4A, 0x00D4C),
+    (0x00DDA, 0x00DDA),
+    (0x00DDC, 0x00DDE),
+    (0x00F43, 0x00F43),
+    (0x00F4D, 0x00F4D),
+    (0x00F52, 0x00F52),
+    (0x00F57, 0x00F57),
+    (0x00F5C, 0x00F5C),
+    (0x00F69, 0x00F69),
+    (0x00F73, 0x00F73),
+    (0x00F75, 0x00F76),
+    (0x00F78, 0x00F78),
+    (0x00F81, 0x00F81),
+    (0x00F93, 0x00F93),
+    (0x00F9D, 0x00F9D),
+    (0x00FA2, 0x00FA2),
+    (0x00FA7, 0x00FA7),
+    (0x00FAC, 0x00FAC),
+    (0x00FB9, 0x00FB9),
+    (0x01026, 0x01026),
+    (0x01B06, 0x01B06),
+    (0x01B08, 0x01B08),
+    (0x01B0A, 0x01B0A),
+    (0x01B0C, 0x01B0C),
+    (0x01B0E, 0x01B0E),
+    (0x01B12, 0x01B12),
+    (0x01B3B, 0x01B3B),
+    (0x01B3D, 0x01B3D),
+    (0x01B40, 0x01B41),
+    (0x01B43, 0x01B43),
+    (0x01E00, 0x01E99),
+    (0x01E9B, 0x01E9B),
+    (0x01EA0, 0x01EF9),
+    (0x01F00, 0x01F15),
+    (0x01F18, 0x01F1D),
+    (0x01F20, 0x01F45),
+    (0x01F48, 0x01F4D),
+    (0x01F50, 0x01F57),
+    (0x01F59, 0x01F59),
+    (0x01F5B, 0x01F5B),
+    (0x01F5D, 0x01F5D),
+    (0x01F5F, 0x01F7D),
+    (0x01F80, 0x01FB4),
+    (0x01FB6, 0x01FBC),
+    (0x01FBE, 0x01FBE),
+    (0x01FC1, 0x01FC4),
+    (0x01FC6, 0x01FD3),
+    (0x01FD6, 0x01FDB),
+    (0x01FDD, 0x01FEF),
+    (0x01FF2, 0x01FF4),
+    (0x01FF6, 0x01FFD),
+    (0x02000, 0x02001),
+    (0x02126, 0x02126),
+    (0x0212A, 0x0212B),
+    (0x0219A, 0x0219B),
+    (0x021AE, 0x021AE),
+    (0x021CD, 0x021CF),
+    (0x02204, 0x02204),
+    (0x02209, 0x02209),
+    (0x0220C, 0x0220C),
+    (0x02224, 0x02224),
+    (0x02226, 0x02226),
+    (0x02241, 0x02241),
+    (0x02244, 0x02244),
+    (0x02247, 0x02247),
+    (0x02249, 0x02249),
+    (0x02260, 0x02260),
+    (0x02262, 0x02262),
+    (0x0226D, 0x02271),
+    (0x02274, 0x02275),
+    (0x02278, 0x02279),
+    (0x02280, 0x02281),
+    (0x02284, 0x02285),
+    (0x02288, 0x02289),
+    (0x022AC, 0x022AF),
+    (0x022E0, 0x022E3),
+    (0x022EA, 0x022ED),
+    (0x02329, 0x0232A),
+    (0x02ADC, 0x02ADC),
+    (0x0304C, 0x0304C),
+    (0x0304E, 0x0304E),
+    (0x03050, 0x03050),
+    (0x03052, 0x03052),
+    (0x03054, 0x03054),
+    (0x03056, 0x03056),
+    (0x03058, 0x03058),
+    (0x0305A, 0x0305A),
+    (0x0305C, 0x0305C),
+    (0x0305E, 0x0305E),
+    (0x03060, 0x03060),
+    (0x03062, 0x03062),
+    (0x03065, 0x03065),
+    (0x03067, 0x03067),
+    (0x03069, 0x03069),
+    (0x03070, 0x03071),
+    (0x03073, 0x03074),
+    (0x03076, 0x03077),
+    (0x03079, 0x0307A),
+    (0x0307C, 0x0307D),
+    (0x03094, 0x03094),
+    (0x0309E, 0x0309E),
+    (0x030AC, 0x030AC),
+    (0x030AE, 0x030AE),
+    (0x030B0, 0x030B0),
+    (0x030B2, 0x030B2),
+    (0x030B4, 0x030B4),
+    (0x030B6, 0x030B6),
+    (0x030B8, 0x030B8),
+    (0x030BA, 0x030BA),
+    (0x030BC, 0x030BC),
+    (0x030BE, 0x030BE),
+    (0x030C0, 0x030C0),
+    (0x030C2, 0x030C2),
+    (0x030C5, 0x030C5),
+    (0x030C7, 0x030C7),
+    (0x030C9, 0x030C9),
+    (0x030D0, 0x030D1),
+    (0x030D3, 0x030D4),
+    (0x030D6, 0x030D7),
+    (0x030D9, 0x030DA),
+    (0x030DC, 0x030DD),
+    (0x030F4, 0x030F4),
+    (0x030F7, 0x030FA),
+    (0x030FE, 0x030FE),
+    (0x0AC00, 0x0D7A3),
+    (0x0F900, 0x0FA0D),
+    (0x0FA10, 0x0FA10),
+    (0x0FA12, 0x0FA12),
+    (0x0FA15, 0x0FA1E),
+    (0x0FA20, 0x0FA20),
+    (0x0FA22, 0x0FA22),
+    (0x0FA25, 0x0FA26),
+    (0x0FA2A, 0x0FA6D),
+    (0x0FA70, 0x0FAD9),
+    (0x0FB1D, 0x0FB1D),
+    (0x0FB1F, 0x0FB1F),
+    (0x0FB2A, 0x0FB36),
+    (0x0FB38, 0x0FB3C),
+    (0x0FB3E, 0x0FB3E),
+    (0x0FB40, 0x0FB41),
+    (0x0FB43, 0x0FB44),
+    (0x0FB46, 0x0FB4E),
+    (0x1109A, 0x1109A),
+    (0x1109C, 0x1109C),
+    (0x110AB, 0x110AB),
+    (0x1112E, 0x1112F),
+    (0x1134B, 0x1134C),
+    (0x114BB, 0x114BC),
+    (0x114BE, 0x114BE),
+    (0x115BA, 0x115BB),
+    (0x11938, 0x11938),
+    (0x1D15E, 0x1D164),
+    (0x1D1BB, 0x1D1C0),
+    (0x2F800, 0x2FA1D),
+];
@@ -1,7 +1,66 @@
 use ggus::GGufMetaError;
 
-/// 将字符串中的所有空格替换为特殊的 Unicode 字符 U+2581（下八分之一块）
-pub fn llama_escape_whitespace(text: &mut String) {
-    // 使用 Rust 的 replace_all 方法替换所有空格
-    *text = text.replace(" ", "\u{2581}");
+/// 默认的空格替换字符 U+2581（下八分之一块）
+pub const META_SPACE: char = '\u{2581}';
+
+/// 将字符串中的所有空格替换为 `meta_space`，通常是 [`META_SPACE`]
+pub fn llama_escape_whitespace(text: &mut String, meta_space: char) {
+    *text = text.replace(' ', meta_space.encode_utf8(&mut [0; 4]));
+}
+
+/// [`llama_escape_whitespace`] 的逆过程，将 `meta_space` 还原为空格
+pub fn llama_unescape_whitespace(text: &mut String, meta_space: char) {
+    *text = text.replace(meta_space, " ");
+}
+
+/// 清理解码结果中的多余空格：删除标点和缩写前的空格，合并连续的空格
+pub fn llama_clean_spaces(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut ans = String::with_capacity(text.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if c == ' ' {
+            let next = &chars[i + 1..];
+            let skip = match next {
+                [' ', ..] => true,
+                ['?' | '!' | '.' | ',', ..] => !ans.is_empty(),
+                ['\'', 's' | 'm', ..] | ['\'', 'r' | 'v', 'e', ..] => !ans.is_empty(),
+                _ => false,
+            };
+            if skip {
+                continue;
+            }
+        }
+        ans.push(c)
+    }
+    ans
+}
+
+#[cfg(test)]
+mod untils_tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_spaces() {
+        assert_eq!(llama_clean_spaces("Hello , world !"), "Hello, world!");
+        assert_eq!(llama_clean_spaces("it 's  fine ."), "it's fine.");
+        assert_eq!(
+            llama_clean_spaces("we 're here , do n't"),
+            "we're here, do n't"
+        );
+        assert_eq!(llama_clean_spaces(" , leading"), " , leading");
+    }
+
+    #[test]
+    fn test_escape_whitespace_meta_space() {
+        let mut text = "hello big world".to_string();
+        llama_escape_whitespace(&mut text, META_SPACE);
+        assert_eq!(text, "hello▁big▁world");
+        llama_unescape_whitespace(&mut text, META_SPACE);
+        assert_eq!(text, "hello big world");
+
+        llama_escape_whitespace(&mut text, 'Ġ');
+        assert_eq!(text, "helloĠbigĠworld");
+        llama_unescape_whitespace(&mut text, 'Ġ');
+        assert_eq!(text, "hello big world");
+    }
 }
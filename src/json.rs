@@ -0,0 +1,305 @@
+//! 用于读写词表文件的最小 json 实现。
+
+/// json 值，对象保持键的原始顺序
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// 按键查找对象成员
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// 紧凑格式输出，不含多余空白
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => write_str(f, s),
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?
+                    }
+                    write!(f, "{item}")?
+                }
+                f.write_str("]")
+            }
+            Self::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{value}")?
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_str(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// 解析 json 文本，失败时返回出错位置的字节偏移
+pub(crate) fn parse(text: &str) -> Result<Value, usize> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let ans = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos == parser.text.len() {
+        Ok(ans)
+    } else {
+        Err(parser.pos)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.pos) {
+            self.pos += 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), usize> {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.pos)
+        }
+    }
+
+    fn literal(&mut self, lit: &[u8], value: Value) -> Result<Value, usize> {
+        if self.text[self.pos..].starts_with(lit) {
+            self.pos += lit.len();
+            Ok(value)
+        } else {
+            Err(self.pos)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, usize> {
+        match self.peek().ok_or(self.pos)? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Value::String),
+            b't' => self.literal(b"true", Value::Bool(true)),
+            b'f' => self.literal(b"false", Value::Bool(false)),
+            b'n' => self.literal(b"null", Value::Null),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => Err(self.pos),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, usize> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.pos);
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    break Ok(Value::Object(members));
+                }
+                _ => break Err(self.pos),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, usize> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    break Ok(Value::Array(items));
+                }
+                _ => break Err(self.pos),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, usize> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.text.get(self.pos) {
+            self.pos += 1
+        }
+        // 数字只包含 ascii 字符
+        let s = unsafe { std::str::from_utf8_unchecked(&self.text[start..self.pos]) };
+        s.parse().map(Value::Number).map_err(|_| start)
+    }
+
+    fn hex4(&mut self) -> Result<u32, usize> {
+        let hex = self.text.get(self.pos..self.pos + 4).ok_or(self.pos)?;
+        let hex = std::str::from_utf8(hex).map_err(|_| self.pos)?;
+        let ans = u32::from_str_radix(hex, 16).map_err(|_| self.pos)?;
+        self.pos += 4;
+        Ok(ans)
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        self.expect(b'"')?;
+        let mut ans = Vec::new();
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.text.get(self.pos) {
+                if b == b'"' || b == b'\\' {
+                    break;
+                }
+                self.pos += 1
+            }
+            ans.extend_from_slice(&self.text[start..self.pos]);
+            match self.text.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    // 输入是 &str，转义之外的部分总是合法的 utf-8
+                    break Ok(String::from_utf8(ans).unwrap());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let esc = *self.text.get(self.pos).ok_or(self.pos)?;
+                    self.pos += 1;
+                    let c = match esc {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let pos = self.pos;
+                            let mut cpt = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&cpt) {
+                                if !self.text[self.pos..].starts_with(b"\\u") {
+                                    return Err(pos);
+                                }
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(pos);
+                                }
+                                cpt = 0x10000 + ((cpt - 0xd800) << 10) + (low - 0xdc00)
+                            }
+                            char::from_u32(cpt).ok_or(pos)?
+                        }
+                        _ => return Err(self.pos - 1),
+                    };
+                    ans.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                _ => break Err(self.pos),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"a": [1, -2.5e1, true, null], "b": "x\"中\ud83d\ude00"} "#).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Value::Array(vec![
+                Value::Number(1.),
+                Value::Number(-25.),
+                Value::Bool(true),
+                Value::Null
+            ]))
+        );
+        assert_eq!(value.get("b").and_then(Value::as_str), Some("x\"中😀"));
+        assert_eq!(parse("[1, 2"), Err(5));
+        assert_eq!(parse("{} x"), Err(3));
+    }
+
+    #[test]
+    fn test_display() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":"x\"\\\n\u0001中"}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(parse(&value.to_string()), Ok(value));
+    }
+}
@@ -1,14 +1,25 @@
 #![feature(linked_list_cursors)]
 mod bpe;
+mod cached;
 mod gpt2;
+mod json;
 mod lpe;
+mod specials;
 mod tokeneer;
 mod vocab;
-pub use bpe::Bpe;
-pub use gpt2::Gpt2Tokenizer;
-pub use lpe::Lpe;
-pub use tokeneer::Tokeneer;
-pub use vocab::TokenType;
+pub use bpe::{Bpe, EncodeScratch, MergeEvent, ScoreOrder, UnkError};
+pub use cached::CachedMethod;
+pub use gpt2::{
+    DecodeStream, Fragment, Gpt2Config, Gpt2Error, Gpt2Tokenizer, NormalizationFlags,
+    PretokenizeKind, Segment, SessionStats, SpecialParseMode, SpecialTokens,
+};
+pub use lpe::{Lpe, LpeStats};
+pub use specials::SpecialsWrapper;
+pub use tokeneer::{Encoding, Tokeneer};
+pub use vocab::{TokenType, VocabDiff, VocabError};
+
+use std::{borrow::Cow, collections::HashMap};
+use vocab::Fingerprint;
 
 /// `utok` for token id.
 #[allow(non_camel_case_types)]
@@ -19,5 +30,188 @@ pub trait Method {
     fn vocab_size(&self) -> usize;
     fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)>;
     fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_;
+    /// 解码一个词，返回的字节借用自词表，不会分配内存
     fn decode(&self, token: utok) -> &[u8];
+    /// 查找与 `piece` 完全相同的词，`piece` 与 [`Method::decode`] 的结果形式相同
+    fn token_of(&self, piece: &str) -> Option<utok>;
+    /// 将 [`Method::decode`] 的结果追加到 `buf`，解码长序列时可以复用同一个缓冲区
+    fn decode_into(&self, token: utok, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.decode(token))
+    }
+    /// [`Method::decode`] 的结果的字节长度，用于预先分配缓冲区
+    fn decoded_len(&self, token: utok) -> usize {
+        self.decode(token).len()
+    }
+    /// 词表中最长的词的字节长度，用于确定缓冲区的大小
+    fn max_token_len(&self) -> usize {
+        self.iter_vocab()
+            .map(|(_, piece)| piece.len())
+            .max()
+            .unwrap_or(0)
+    }
+    /// 按词序号遍历整个词表
+    fn iter_vocab(&self) -> impl Iterator<Item = (utok, Cow<'_, [u8]>)> + '_;
+    /// 编码文本并截断到至多 `max_tokens` 个词。
+    ///
+    /// 截断作用于完整编码的结果，保留的词与完整编码中的对应部分相同；
+    /// 截断处的词可能只是某个预分词片段的一部分。
+    fn encode_truncated(&self, text: &str, max_tokens: usize, side: TruncSide) -> Vec<utok> {
+        let mut tokens = self.encode(text).into_iter().collect::<Vec<_>>();
+        match side {
+            TruncSide::Left => {
+                let n = tokens.len().saturating_sub(max_tokens);
+                tokens.drain(..n);
+            }
+            TruncSide::Right => tokens.truncate(max_tokens),
+        }
+        tokens
+    }
+    /// 计算分词器的稳定指纹，内容相同的分词器总是产生相同的指纹，可用作缓存的键
+    fn fingerprint(&self) -> u64 {
+        Fingerprint::vocab(self).finish()
+    }
+    /// 编码任意字节序列，不要求是合法的 utf-8。
+    ///
+    /// 默认实现将合法的 utf-8 片段按文本编码，非法字节编码为 unk。
+    fn encode_bytes(&self, bytes: &[u8]) -> Vec<utok> {
+        let mut ans = Vec::new();
+        for chunk in bytes.utf8_chunks() {
+            ans.extend(self.encode(chunk.valid()));
+            ans.extend(chunk.invalid().iter().map(|_| self.unk_token()))
+        }
+        ans
+    }
+    /// 分别解码每个词，用于逐词对齐显示，非法的 utf-8 替换为 `U+FFFD`
+    fn decode_each(&self, tokens: &[utok]) -> Vec<String> {
+        tokens
+            .iter()
+            .map(|&t| String::from_utf8_lossy(self.decode(t)).into_owned())
+            .collect()
+    }
+    /// 统计语料编码后每个词出现的次数，用于分析词表的覆盖情况
+    fn token_histogram(&self, texts: &[&str]) -> HashMap<utok, u64> {
+        let mut ans = HashMap::new();
+        for text in texts {
+            for t in self.encode(text) {
+                *ans.entry(t).or_default() += 1
+            }
+        }
+        ans
+    }
+    /// 将 [`Method::decode`] 得到的字节还原为显示用的字节，默认不做处理。
+    ///
+    /// 词表中以替换字符保存字节的分词器（如 GPT-2 的字节映射）在此还原原始字节。
+    fn pre_decode<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        Cow::Borrowed(bytes)
+    }
+    /// 编码 UTF-16 文本，单独的代理项替换为 `U+FFFD`
+    fn encode_utf16(&self, units: &[u16]) -> Vec<utok> {
+        self.encode(&String::from_utf16_lossy(units))
+            .into_iter()
+            .collect()
+    }
+    /// 解码为 UTF-16 文本，经过 [`Method::pre_decode`]，非法的 utf-8 替换为 `U+FFFD`
+    fn decode_utf16(&self, tokens: &[utok]) -> Vec<u16> {
+        let bytes = tokens
+            .iter()
+            .flat_map(|&t| self.decode(t))
+            .copied()
+            .collect::<Vec<_>>();
+        String::from_utf8_lossy(&self.pre_decode(&bytes))
+            .encode_utf16()
+            .collect()
+    }
+    /// 无损解码，经过 [`Method::pre_decode`] 还原原始字节。
+    ///
+    /// 序列中包含 unk 或超出词表范围的词时无法确定原文，返回 `None`。
+    fn decode_lossless(&self, tokens: &[utok]) -> Option<Vec<u8>> {
+        let unk = self.unk_token();
+        let mut bytes = Vec::new();
+        for &t in tokens {
+            if t == unk || t as usize >= self.vocab_size() {
+                return None;
+            }
+            bytes.extend_from_slice(self.decode(t))
+        }
+        Some(self.pre_decode(&bytes).into_owned())
+    }
+    /// 按词的字节内容建立到另一个词表的序号映射，`other` 中不存在的词映射到它的 unk
+    fn remap_to(&self, other: &impl Method) -> HashMap<utok, utok> {
+        let index = other
+            .iter_vocab()
+            .map(|(t, piece)| (piece.into_owned(), t))
+            .collect::<HashMap<_, _>>();
+        let unk = other.unk_token();
+        self.iter_vocab()
+            .map(|(t, piece)| (t, index.get(&*piece).copied().unwrap_or(unk)))
+            .collect()
+    }
+    /// 按词的字节内容比较两个词表，用于检查重新导出的模型词表是否一致。
+    ///
+    /// 内容重复的词只比较第一个。
+    fn diff(&self, other: &impl Method) -> VocabDiff {
+        fn index<'a>(vocab: impl Iterator<Item = (utok, Cow<'a, [u8]>)>) -> HashMap<Vec<u8>, utok> {
+            let mut ans = HashMap::new();
+            for (t, piece) in vocab {
+                ans.entry(piece.into_owned()).or_insert(t);
+            }
+            ans
+        }
+        let ours = index(self.iter_vocab());
+        let theirs = index(other.iter_vocab());
+        let mut ans = VocabDiff::default();
+        for (piece, &t) in &ours {
+            match theirs.get(piece) {
+                Some(&u) if u != t => ans.id_mismatch.push((t, u)),
+                Some(_) => {}
+                None => ans.only_in_self.push(t),
+            }
+        }
+        ans.only_in_other = theirs
+            .iter()
+            .filter(|(piece, _)| !ours.contains_key(*piece))
+            .map(|(_, &t)| t)
+            .collect();
+        ans.only_in_self.sort_unstable();
+        ans.only_in_other.sort_unstable();
+        ans.id_mismatch.sort_unstable();
+        ans
+    }
+    /// 列出解码后不能重新编码为自身的词，按序号升序排列，用于检查词表的完整性。
+    ///
+    /// 包括合词规则不可达的词、内容重复的词以及 unk 等特殊词。
+    fn self_consistency_report(&self) -> Vec<utok> {
+        self.iter_vocab()
+            .filter(|(t, piece)| self.encode_bytes(&self.pre_decode(piece)) != [*t])
+            .map(|(t, _)| t)
+            .collect()
+    }
+    /// 编码文本，并由调用者决定是否添加 bos 和 eos。
+    ///
+    /// 没有 bos/eos 概念的分词器忽略这两个参数。
+    fn encode_with_special(&self, text: &str, add_bos: bool, add_eos: bool) -> Vec<utok> {
+        let _ = (add_bos, add_eos);
+        self.encode(text).into_iter().collect()
+    }
+}
+
+/// 文本中出现词表无法匹配的字符时的处理方式
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum FallbackMode {
+    /// 逐字节编码为单字节词，词表中没有对应的单字节词时为 unk
+    #[default]
+    ByteFallback,
+    /// 每个无法匹配的字符编码为一个 unk
+    Unk,
+    /// 丢弃无法匹配的字符
+    Skip,
+}
+
+/// 截断编码结果时保留的一侧
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TruncSide {
+    /// 丢弃开头，保留最后的词
+    Left,
+    /// 丢弃结尾，保留最前的词
+    Right,
 }
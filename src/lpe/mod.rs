@@ -1,8 +1,8 @@
-﻿//! l-p-e for Longest Prefix Encoding
+//! l-p-e for Longest Prefix Encoding
 
 use crate::{
-    Method, utok,
-    vocab::{CollectedVocab, CompressedVocab, TokenType},
+    FallbackMode, Method, utok,
+    vocab::{CollectedVocab, CompressedVocab, TokenType, VocabError, display_piece},
 };
 use patricia_tree::PatriciaMap;
 use std::{
@@ -25,6 +25,29 @@ pub struct Lpe {
     special: Box<[utok]>,
     /// token: <unk>
     unk: utok,
+    /// 词表是否经过 utf-8 到字节的映射
+    map_utf8: bool,
+    /// 无法匹配的字符的处理方式
+    fallback: FallbackMode,
+    /// 匹配的词的最大字节数
+    max_token_len: Option<usize>,
+    /// 每个词的分数，不带分数构造时为 `None`
+    scores: Option<Box<[f32]>>,
+}
+
+/// [`Lpe`] 各部分的内存占用
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LpeStats {
+    /// 压缩后的词内容字节数
+    pub vocab_bytes: usize,
+    /// 词的元信息字节数
+    pub token_meta_bytes: usize,
+    /// 前缀树中的词数，相同内容的词只计一次
+    pub trie_entries: usize,
+    /// 前缀树键的总字节数，是前缀树标签占用的上限
+    pub trie_key_bytes: usize,
+    /// 特殊词的数量
+    pub special_count: usize,
 }
 
 impl Lpe {
@@ -47,6 +70,39 @@ impl Lpe {
         )
     }
 
+    /// 解析词表文件，校验每一行都是合法的 utf-8。
+    ///
+    /// 每行一个词，词两侧的引号是可选的。
+    pub fn from_vocabs_txt_checked(txt: &[u8]) -> Result<Self, VocabError> {
+        let vocabs = lines(txt)
+            .enumerate()
+            .map(|(i, line)| {
+                std::str::from_utf8(line)
+                    .map(unquote)
+                    .map_err(|_| VocabError::InvalidUtf8 { line: i + 1 })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_vocab_lines(vocabs.iter().map(|s| s.as_bytes())))
+    }
+
+    /// 解析词表文件，非法的 utf-8 序列替换为 `U+FFFD`。
+    pub fn from_vocabs_txt_lossy(txt: &[u8]) -> Self {
+        let vocabs = lines(txt)
+            .map(|line| match String::from_utf8_lossy(line) {
+                Cow::Borrowed(s) => Cow::Borrowed(unquote(s)),
+                Cow::Owned(s) => Cow::Owned(unquote(&s).to_string()),
+            })
+            .collect::<Vec<_>>();
+        Self::from_vocab_lines(vocabs.iter().map(|s| s.as_bytes()))
+    }
+
+    fn from_vocab_lines<'a>(vocabs: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self::from_collected_vocab(
+            CollectedVocab::collect(vocabs, std::iter::repeat(TokenType::Normal), 0),
+            false,
+        )
+    }
+
     pub fn new<'a>(
         vocabs: impl IntoIterator<Item = &'a [u8]>,
         token_type: impl IntoIterator<Item = TokenType>,
@@ -56,6 +112,64 @@ impl Lpe {
         Self::from_collected_vocab(CollectedVocab::collect(vocabs, token_type, unk), map_utf8)
     }
 
+    /// 与 [`Lpe::new`] 相同，同时保存每个词的分数，分数不参与匹配
+    pub fn with_scores<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        scores: impl IntoIterator<Item = f32>,
+        token_type: impl IntoIterator<Item = TokenType>,
+        unk: utok,
+        map_utf8: bool,
+    ) -> Self {
+        let (vocab, scores) = CollectedVocab::collect_with_scores(vocabs, scores, token_type, unk);
+        let mut ans = Self::from_collected_vocab(vocab, map_utf8);
+        ans.scores = Some(scores.into_boxed_slice());
+        ans
+    }
+
+    /// 词的分数，不带分数构造的词表返回 `None`
+    #[inline]
+    pub fn token_score(&self, token: utok) -> Option<f32> {
+        self.scores.as_ref()?.get(token as usize).copied()
+    }
+
+    /// 使用已有的前缀树构造，作为通用的最长前缀匹配引擎。
+    ///
+    /// `vocabs` 按序号给出每个词的内容，用于解码；`trie` 中的值必须是 `vocabs` 中的序号，
+    /// 不在 `trie` 中的词不会被匹配。`bytes` 是前缀树无法匹配时每个字节回退到的词。
+    pub fn from_trie<'a>(
+        trie: PatriciaMap<utok>,
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        bytes: [utok; 256],
+        unk: utok,
+    ) -> Self {
+        let vocabs = vocabs.into_iter().collect::<Vec<_>>();
+        let n = vocabs.len() as utok;
+        assert!(unk < n, "unk out of vocab range");
+        assert!(
+            trie.values().chain(&bytes).all(|&t| t < n),
+            "token out of vocab range"
+        );
+
+        let total_len = vocabs.iter().map(|s| s.len()).sum();
+        let CompressedVocab { vocabs, slices } = CompressedVocab::new(&vocabs, total_len);
+        let tokens = slices
+            .into_iter()
+            .map(|(off, len)| (off as u32, len as u32))
+            .collect::<Box<_>>();
+        Self {
+            vocabs,
+            tokens,
+            trie,
+            bytes: Box::new(bytes),
+            special: Box::new([]),
+            unk,
+            map_utf8: false,
+            fallback: FallbackMode::ByteFallback,
+            max_token_len: None,
+            scores: None,
+        }
+    }
+
     fn from_collected_vocab(vocab: CollectedVocab, map_utf8: bool) -> Self {
         let CollectedVocab {
             vocabs,
@@ -80,8 +194,12 @@ impl Lpe {
                             if let Some(&c) = MAP_UTF8_TO_BYTE.get(&piece) {
                                 utf8.push(c)
                             } else {
-                                let c = c as u8;
-                                utf8.extend_from_slice(format!("[UNK_BYTE_{c:#02x}]").as_bytes())
+                                let mut buf = [0u8; 4];
+                                for b in c.encode_utf8(&mut buf).bytes() {
+                                    utf8.extend_from_slice(
+                                        format!("[UNK_BYTE_{b:#04x}]").as_bytes(),
+                                    )
+                                }
                             }
                         }
                         Cow::Owned(utf8)
@@ -120,7 +238,88 @@ impl Lpe {
             bytes,
             special,
             unk,
+            map_utf8,
+            fallback: FallbackMode::ByteFallback,
+            max_token_len: None,
+            scores: None,
+        }
+    }
+
+    /// 限制最长前缀匹配的词的字节数，超过限制的词不会被匹配，`None` 表示不限制
+    pub fn set_max_token_len(&mut self, len: Option<usize>) {
+        self.max_token_len = len
+    }
+
+    /// 设置无法匹配的字符的处理方式，默认为 [`FallbackMode::ByteFallback`]
+    pub fn set_fallback(&mut self, mode: FallbackMode) {
+        self.fallback = mode
+    }
+
+    /// 解码 token 为原始字节。
+    ///
+    /// 对于以 `map_utf8` 构造的词表，映射表中不存在的字符保存为 `[UNK_BYTE_0xNN]`，
+    /// 此方法将其还原为对应的字节；其他情况与 [`Method::decode`] 相同。
+    pub fn decode_text(&self, token: utok) -> Cow<'_, [u8]> {
+        let piece = self.token(token);
+        if !self.map_utf8 || memchr::memmem::find(piece, UNK_BYTE_PREFIX).is_none() {
+            return Cow::Borrowed(piece);
+        }
+
+        let mut ans = Vec::with_capacity(piece.len());
+        let mut rest = piece;
+        while let Some(pos) = memchr::memmem::find(rest, UNK_BYTE_PREFIX) {
+            ans.extend_from_slice(&rest[..pos]);
+            rest = &rest[pos..];
+            match parse_unk_byte(rest) {
+                Some((b, len)) => {
+                    ans.push(b);
+                    rest = &rest[len..]
+                }
+                None => {
+                    ans.extend_from_slice(UNK_BYTE_PREFIX);
+                    rest = &rest[UNK_BYTE_PREFIX.len()..]
+                }
+            }
         }
+        ans.extend_from_slice(rest);
+        Cow::Owned(ans)
+    }
+
+    /// 所有是 `text` 前缀的词及其字节数，按长度从短到长排列。
+    ///
+    /// 是最长前缀匹配的推广，单字节词也包含在内，用于补全或约束解码。
+    pub fn prefix_tokens(&self, text: &str) -> Vec<(utok, usize)> {
+        let mut ans = self
+            .trie
+            .common_prefixes(text.as_bytes())
+            .map(|(pre, &tok)| (tok, pre.len()))
+            .collect::<Vec<_>>();
+        if let Some(&b) = text.as_bytes().first() {
+            let tok = self.bytes[b as usize];
+            if tok != self.unk && ans.first().is_none_or(|&(_, len)| len != 1) {
+                ans.insert(0, (tok, 1))
+            }
+        }
+        ans
+    }
+
+    /// 统计词表的内存占用。
+    ///
+    /// 所有存储都是定长的切片，构造后没有多余的容量。
+    pub fn memory_stats(&self) -> LpeStats {
+        LpeStats {
+            vocab_bytes: self.vocabs.len(),
+            token_meta_bytes: size_of_val(&*self.tokens),
+            trie_entries: self.trie.len(),
+            trie_key_bytes: self.trie.keys().map(|k| k.len()).sum(),
+            special_count: self.special.len(),
+        }
+    }
+
+    /// 以便于阅读的形式渲染 token 内容，用于调试词表。
+    #[inline]
+    pub fn token_piece_display(&self, token: utok) -> String {
+        display_piece(self.token(token))
     }
 
     /// token id -> token meta
@@ -148,16 +347,41 @@ impl Method for Lpe {
         })
     }
 
+    #[inline]
     fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
-        let mut text = text.as_bytes();
+        self.encode_bytes(text.as_bytes())
+    }
+    /// 最长前缀匹配直接作用于字节，不要求是合法的 utf-8
+    fn encode_bytes(&self, mut text: &[u8]) -> Vec<utok> {
         let mut tokens = Vec::<utok>::new();
 
         while !text.is_empty() {
-            let (tok, len) = match self.trie.get_longest_common_prefix(text) {
-                Some((pre, tok)) => (*tok, pre.len()),
-                None => (self.bytes[text[0] as usize], 1),
+            let prefix = match self.max_token_len {
+                Some(max) => &text[..text.len().min(max)],
+                None => text,
+            };
+            let len = match self.trie.get_longest_common_prefix(prefix) {
+                Some((pre, &tok)) => {
+                    tokens.push(tok);
+                    pre.len()
+                }
+                None => match self.fallback {
+                    FallbackMode::ByteFallback => {
+                        tokens.push(self.bytes[text[0] as usize]);
+                        1
+                    }
+                    // 非法的 utf-8 字节作为一个字符处理
+                    mode => {
+                        if mode == FallbackMode::Unk {
+                            tokens.push(self.unk)
+                        }
+                        text.utf8_chunks()
+                            .next()
+                            .and_then(|chunk| chunk.valid().chars().next())
+                            .map_or(1, char::len_utf8)
+                    }
+                },
             };
-            tokens.push(tok);
             text = &text[len..]
         }
 
@@ -167,6 +391,58 @@ impl Method for Lpe {
     fn decode(&self, token: utok) -> &[u8] {
         self.token(token)
     }
+    #[inline]
+    fn decoded_len(&self, token: utok) -> usize {
+        self.tokens[token as usize].1 as _
+    }
+    fn max_token_len(&self) -> usize {
+        self.tokens
+            .iter()
+            .map(|&(_, len)| len as usize)
+            .max()
+            .unwrap_or(0)
+    }
+    #[inline]
+    fn token_of(&self, piece: &str) -> Option<utok> {
+        match self.trie.get(piece) {
+            Some(&tok) => Some(tok),
+            // 词表中没有对应单字节词的字节不是一个词
+            None => match *piece.as_bytes() {
+                [b] => Some(self.bytes[b as usize]).filter(|&t| t != self.unk),
+                [..] => None,
+            },
+        }
+    }
+    #[inline]
+    fn iter_vocab(&self) -> impl Iterator<Item = (utok, Cow<'_, [u8]>)> + '_ {
+        (0..self.tokens.len() as utok).map(|t| (t, Cow::Borrowed(self.token(t))))
+    }
+}
+
+/// 按行分割，与 [`str::lines`] 的规则相同
+fn lines(txt: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let txt = txt.strip_suffix(b"\n").unwrap_or(txt);
+    txt.split(|&b| b == b'\n')
+        .filter(move |_| !txt.is_empty())
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+/// 去除两侧的引号，没有成对的引号时保持原样
+fn unquote(line: &str) -> &str {
+    line.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(line)
+}
+
+const UNK_BYTE_PREFIX: &[u8] = b"[UNK_BYTE_0x";
+
+/// 解析 `[UNK_BYTE_0xNN]`，返回字节和转义序列的长度
+fn parse_unk_byte(piece: &[u8]) -> Option<(u8, usize)> {
+    let hex = &piece[UNK_BYTE_PREFIX.len()..];
+    let len = hex.iter().take(3).position(|&c| c == b']')?;
+    let hex = std::str::from_utf8(&hex[..len]).ok()?;
+    let b = u8::from_str_radix(hex, 16).ok()?;
+    Some((b, UNK_BYTE_PREFIX.len() + len + 1))
 }
 
 static MAP_UTF8_TO_BYTE: LazyLock<HashMap<String, u8>> = LazyLock::new(unicode_utf8_to_byte_map);
@@ -225,3 +501,263 @@ fn unicode_cpt_to_utf8(cpt: u32) -> String {
 
     String::from_utf8(bytes).unwrap()
 }
+
+#[cfg(test)]
+mod lpe_tests {
+    use super::*;
+    use crate::VocabDiff;
+
+    #[test]
+    fn test_lpe_token_piece_display() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", "\u{2581}".as_bytes(), b"<0xFF>", b"hello"];
+        let token_type = [
+            TokenType::Unknown,
+            TokenType::Normal,
+            TokenType::Byte,
+            TokenType::Normal,
+        ];
+        let lpe = Lpe::new(vocabs, token_type, 0, false);
+
+        assert_eq!(lpe.token_piece_display(1), "_");
+        assert_eq!(lpe.token_piece_display(2), "<0xFF>");
+        assert_eq!(lpe.token_piece_display(3), "hello")
+    }
+
+    #[test]
+    fn test_lpe_from_vocabs_txt_checked() {
+        let lpe = Lpe::from_vocabs_txt_checked(b"\"<unk>\"\n\"a\"\nb\n\"ab\"\n").unwrap();
+        assert_eq!(lpe.vocab_size(), 4);
+        assert_eq!(lpe.decode(2), b"b");
+        assert_eq!(lpe.encode("abb").into_iter().collect::<Vec<_>>(), [3, 2]);
+
+        assert_eq!(
+            Lpe::from_vocabs_txt_checked(b"\"a\"\n\"\xff\"\n").err(),
+            Some(VocabError::InvalidUtf8 { line: 2 })
+        );
+        let lpe = Lpe::from_vocabs_txt_lossy(b"\"a\"\n\"\xff\"\n");
+        assert_eq!(lpe.decode(1), "\u{FFFD}".as_bytes())
+    }
+
+    #[test]
+    fn test_lpe_map_utf8_decode_text() {
+        // "Ġ" 映射为空格，"中" 不在映射表中，按 utf-8 字节逐个保存为 "[UNK_BYTE_0xNN]"
+        let vocabs: [&[u8]; 4] = [b"<unk>", "\u{120}a".as_bytes(), "中".as_bytes(), b"b"];
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, true);
+        assert_eq!(
+            lpe.decode(2),
+            b"[UNK_BYTE_0xe4][UNK_BYTE_0xb8][UNK_BYTE_0xad]"
+        );
+        assert_eq!(lpe.decode_text(2), "中".as_bytes());
+
+        let tokens = lpe
+            .encode(" a[UNK_BYTE_0xe4][UNK_BYTE_0xb8][UNK_BYTE_0xad]b")
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, [1, 2, 3]);
+
+        let text = tokens
+            .iter()
+            .flat_map(|&t| lpe.decode_text(t).into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(text, " a中b".as_bytes());
+        assert!(matches!(lpe.decode_text(1), Cow::Borrowed(b" a")))
+    }
+
+    #[test]
+    fn test_lpe_token_of() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"ab", b"<0x62>"];
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, false);
+        assert_eq!(lpe.token_of("ab"), Some(2));
+        assert_eq!(lpe.token_of("b"), Some(3));
+        assert_eq!(lpe.token_of("abc"), None);
+        assert_eq!(lpe.token_of("c"), None);
+        assert_eq!(lpe.token_of("<unk>"), None);
+    }
+
+    #[test]
+    fn test_lpe_iter_vocab() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"ab", b"<0x62>"];
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, false);
+        let vocab = lpe.iter_vocab().collect::<Vec<_>>();
+        assert_eq!(vocab.len(), lpe.vocab_size());
+        assert!(vocab.iter().enumerate().all(|(i, (t, _))| i as utok == *t));
+        assert_eq!(&*vocab[3].1, b"b");
+    }
+
+    #[test]
+    fn test_lpe_encode_bytes() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"ab", b"<0xFF>"];
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, false);
+        assert_eq!(lpe.encode_bytes(b"ab\xffa"), [2, 3, 1]);
+        assert_eq!(lpe.encode_bytes(b"\xfe"), [0]);
+    }
+
+    #[test]
+    fn test_lpe_fallback_mode() {
+        let vocabs: [&[u8]; 3] = [b"<unk>", b"a", b"<0xE4>"];
+        let token_type = [TokenType::Unknown, TokenType::Normal, TokenType::Byte];
+        let mut lpe = Lpe::new(vocabs, token_type, 0, false);
+        let text = "a中a".as_bytes();
+        // "中" 的 utf-8 编码是 e4 b8 ad
+        assert_eq!(lpe.encode_bytes(text), [1, 2, 0, 0, 1]);
+        lpe.set_fallback(FallbackMode::Unk);
+        assert_eq!(lpe.encode_bytes(text), [1, 0, 1]);
+        assert_eq!(lpe.encode_bytes(b"a\xff\xffa"), [1, 0, 0, 1]);
+        assert_eq!(lpe.encode_bytes(b"az"), [1, 0]);
+        lpe.set_fallback(FallbackMode::Skip);
+        assert_eq!(lpe.encode_bytes(text), [1, 1]);
+        assert_eq!(lpe.encode_bytes(b"az"), [1]);
+    }
+
+    #[test]
+    fn test_lpe_max_token_len() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"aa", b"aaaa"];
+        let token_type = [
+            TokenType::Unknown,
+            TokenType::Normal,
+            TokenType::Normal,
+            TokenType::Normal,
+        ];
+        let mut lpe = Lpe::new(vocabs, token_type, 0, false);
+        assert_eq!(lpe.encode_bytes(b"aaaaa"), [3, 1]);
+        lpe.set_max_token_len(Some(3));
+        assert_eq!(lpe.encode_bytes(b"aaaaa"), [2, 2, 1]);
+        lpe.set_max_token_len(Some(0));
+        assert_eq!(lpe.encode_bytes(b"a"), [0]);
+        // 词表中最长的词不受匹配长度上限的影响
+        assert_eq!(Method::max_token_len(&lpe), 5);
+    }
+
+    #[test]
+    fn test_lpe_memory_stats() {
+        let lpe = Lpe::from_vocabs_txt_checked(b"<unk>\na\nb\nab\nabc\n").unwrap();
+        let stats = lpe.memory_stats();
+        // "a" "ab" 是 "abc" 的前缀，压缩后只保存 "abc"
+        assert!(stats.vocab_bytes > 0 && stats.vocab_bytes < 11);
+        assert_eq!(stats.token_meta_bytes, 5 * size_of::<(u32, u32)>());
+        // <unk> 不进入前缀树
+        assert_eq!(stats.trie_entries, 4);
+        assert_eq!(stats.trie_key_bytes, 7);
+    }
+
+    #[test]
+    fn test_lpe_from_trie() {
+        let mut trie = PatriciaMap::new();
+        trie.insert("ab", 1);
+        trie.insert("abc", 2);
+        trie.insert("b", 3);
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"ab", b"abc", b"b"];
+        let lpe = Lpe::from_trie(trie, vocabs, [0; 256], 0);
+
+        assert_eq!(lpe.vocab_size(), 4);
+        assert_eq!(
+            lpe.encode("abcabxb").into_iter().collect::<Vec<_>>(),
+            [2, 1, 0, 3]
+        );
+        assert_eq!(lpe.decode(2), b"abc");
+        assert_eq!(lpe.token_of("ab"), Some(1));
+    }
+
+    #[test]
+    fn test_lpe_utf16() {
+        let lpe = Lpe::from_vocabs_txt_checked("<unk>\na\n😀\n".as_bytes()).unwrap();
+        let units = "a😀a".encode_utf16().collect::<Vec<_>>();
+        assert_eq!(units.len(), 4);
+        let tokens = lpe.encode_utf16(&units);
+        assert_eq!(tokens, [1, 2, 1]);
+        assert_eq!(lpe.decode_utf16(&tokens), units);
+
+        // 单独的代理项替换为 U+FFFD
+        assert_eq!(
+            lpe.encode_utf16(&[0xd83d, 'a' as u16]),
+            lpe.encode("\u{FFFD}a").into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_lpe_decode_lossless() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"ab", b"<0xFF>"];
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, false);
+        let tokens = lpe.encode_bytes(b"ab\xffa");
+        assert_eq!(lpe.decode_lossless(&tokens).unwrap(), b"ab\xffa");
+        // 含有 unk 的序列无法还原
+        let tokens = lpe.encode_bytes(b"a\xfe");
+        assert_eq!(tokens, [1, 0]);
+        assert_eq!(lpe.decode_lossless(&tokens), None);
+    }
+
+    #[test]
+    fn test_lpe_remap_to() {
+        let base = Lpe::from_vocabs_txt_checked("<unk>\na\nb\nab\nc\n".as_bytes()).unwrap();
+        let other = Lpe::from_vocabs_txt_checked("<unk>\nab\nb\na\n".as_bytes()).unwrap();
+        let map = base.remap_to(&other);
+        assert_eq!(map.len(), 5);
+        assert_eq!([map[&0], map[&1], map[&2], map[&3]], [0, 3, 2, 1]);
+        // other 中没有 c
+        assert_eq!(map[&4], other.unk_token());
+        let tokens = base.encode("abc").into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            tokens.iter().map(|t| map[t]).collect::<Vec<_>>(),
+            other.encode("abc").into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_lpe_token_score() {
+        let vocabs: [&[u8]; 3] = [b"<unk>", b"a", b"ab"];
+        let lpe = Lpe::with_scores(vocabs, [0., -1., -0.5], [TokenType::Normal; 3], 0, false);
+        assert_eq!(lpe.token_score(1), Some(-1.));
+        assert_eq!(lpe.token_score(2), Some(-0.5));
+        assert_eq!(lpe.token_score(3), None);
+        assert_eq!(lpe.encode("ab").into_iter().collect::<Vec<_>>(), [2]);
+        // 不带分数构造
+        let lpe = Lpe::new(vocabs, [TokenType::Normal; 3], 0, false);
+        assert_eq!(lpe.token_score(1), None);
+    }
+
+    #[test]
+    fn test_lpe_prefix_tokens() {
+        let lpe = Lpe::from_vocabs_txt_checked("<unk>\na\nab\nabc\nb\nabd\n".as_bytes()).unwrap();
+        assert_eq!(lpe.prefix_tokens("abcd"), [(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(lpe.prefix_tokens("ab"), [(1, 1), (2, 2)]);
+        assert_eq!(lpe.prefix_tokens("c"), []);
+        assert_eq!(lpe.prefix_tokens(""), []);
+        // 最长的前缀与最长前缀匹配一致
+        assert_eq!(
+            lpe.prefix_tokens("abdx").last().map(|&(t, _)| t),
+            lpe.encode("abdx").into_iter().next()
+        );
+
+        // 单字节词不在前缀树中
+        let vocabs: [&[u8]; 3] = [b"<unk>", b"<0x61>", b"ab"];
+        let types = [TokenType::Normal, TokenType::Byte, TokenType::Normal];
+        let lpe = Lpe::new(vocabs, types, 0, false);
+        assert_eq!(lpe.prefix_tokens("abc"), [(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_lpe_diff() {
+        let base = Lpe::from_vocabs_txt_checked("<unk>\na\nb\nab\nc\n".as_bytes()).unwrap();
+        let other = Lpe::from_vocabs_txt_checked("<unk>\na\nab\nb\nd\nbc\n".as_bytes()).unwrap();
+        assert_eq!(
+            base.diff(&other),
+            VocabDiff {
+                only_in_self: vec![4],
+                only_in_other: vec![4, 5],
+                id_mismatch: vec![(2, 3), (3, 2)],
+            }
+        );
+        assert!(base.diff(&base).is_empty());
+    }
+
+    #[test]
+    fn test_lpe_decoded_len() {
+        let vocabs: [&[u8]; 4] = [b"<unk>", b"a", b"ab", b"<0x62>"];
+        for map_utf8 in [false, true] {
+            let lpe = Lpe::new(vocabs, [TokenType::Normal; 4], 0, map_utf8);
+            for t in 0..lpe.vocab_size() as utok {
+                assert_eq!(lpe.decoded_len(t), lpe.decode(t).len())
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! 为没有 bos/eos 概念的分词器添加首尾标记。
+
+use crate::{Method, utok};
+use std::borrow::Cow;
+
+/// 在编码结果的首尾添加 bos/eos 的适配器，其余行为与内部的分词器相同
+pub struct SpecialsWrapper<M> {
+    method: M,
+    bos: Option<utok>,
+    eos: Option<utok>,
+}
+
+impl<M> SpecialsWrapper<M> {
+    /// 包装分词器，`None` 表示不添加对应的标记
+    pub fn new(method: M, bos: Option<utok>, eos: Option<utok>) -> Self {
+        Self { method, bos, eos }
+    }
+
+    #[inline]
+    pub fn bos(&self) -> Option<utok> {
+        self.bos
+    }
+
+    #[inline]
+    pub fn eos(&self) -> Option<utok> {
+        self.eos
+    }
+
+    #[inline]
+    pub fn internal(&self) -> &M {
+        &self.method
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.method
+    }
+
+    fn frame(&self, tokens: impl IntoIterator<Item = utok>, bos: bool, eos: bool) -> Vec<utok> {
+        let bos = self.bos.filter(|_| bos);
+        let eos = self.eos.filter(|_| eos);
+        bos.into_iter().chain(tokens).chain(eos).collect()
+    }
+}
+
+impl<M: Method> Method for SpecialsWrapper<M> {
+    #[inline]
+    fn unk_token(&self) -> utok {
+        self.method.unk_token()
+    }
+    #[inline]
+    fn vocab_size(&self) -> usize {
+        self.method.vocab_size()
+    }
+    #[inline]
+    fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)> {
+        self.method.internal_special()
+    }
+    #[inline]
+    fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
+        self.frame(self.method.encode(text), true, true)
+    }
+    #[inline]
+    fn decode(&self, token: utok) -> &[u8] {
+        self.method.decode(token)
+    }
+    #[inline]
+    fn decoded_len(&self, token: utok) -> usize {
+        self.method.decoded_len(token)
+    }
+    #[inline]
+    fn token_of(&self, piece: &str) -> Option<utok> {
+        self.method.token_of(piece)
+    }
+    #[inline]
+    fn iter_vocab(&self) -> impl Iterator<Item = (utok, Cow<'_, [u8]>)> + '_ {
+        self.method.iter_vocab()
+    }
+    fn encode_bytes(&self, bytes: &[u8]) -> Vec<utok> {
+        self.frame(self.method.encode_bytes(bytes), true, true)
+    }
+    fn pre_decode<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        self.method.pre_decode(bytes)
+    }
+    fn encode_with_special(&self, text: &str, add_bos: bool, add_eos: bool) -> Vec<utok> {
+        self.frame(self.method.encode(text), add_bos, add_eos)
+    }
+}
+
+#[cfg(test)]
+mod specials_tests {
+    use super::*;
+    use crate::{Bpe, TokenType};
+
+    #[test]
+    fn test_specials_wrapper() {
+        let vocabs = ["<unk>", "<s>", "</s>", "a", "b", "ab"];
+        let token_type = [
+            TokenType::Unknown,
+            TokenType::Control,
+            TokenType::Control,
+            TokenType::Normal,
+            TokenType::Normal,
+            TokenType::Normal,
+        ];
+        let bpe = Bpe::new(vocabs, [0., 0., 0., -1., -1., 0.], token_type, 0);
+        let inner = bpe.encode("ab").into_iter().collect::<Vec<_>>();
+        assert_eq!(inner, [5]);
+
+        let wrapper = SpecialsWrapper::new(bpe, Some(1), Some(2));
+        assert_eq!(
+            wrapper.encode("ab").into_iter().collect::<Vec<_>>(),
+            [1, 5, 2]
+        );
+        assert_eq!(wrapper.encode("").into_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(wrapper.encode_bytes(b"ab"), [1, 5, 2]);
+        assert_eq!(wrapper.encode_with_special("ab", true, false), [1, 5]);
+        assert_eq!(wrapper.encode_with_special("ab", false, false), inner);
+        assert_eq!(wrapper.decode(5), b"ab");
+
+        // 只添加 eos
+        let wrapper = SpecialsWrapper::new(wrapper.into_inner(), None, Some(2));
+        assert_eq!(wrapper.encode("ab").into_iter().collect::<Vec<_>>(), [5, 2]);
+    }
+}
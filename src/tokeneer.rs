@@ -1,8 +1,9 @@
-﻿use crate::{Method, utok};
+use crate::{Gpt2Tokenizer, Method, PretokenizeKind, utok};
 use regex::Regex;
 use std::{
     collections::{HashMap, HashSet},
-    ops::Deref,
+    iter::zip,
+    ops::{Deref, Range},
     slice::from_ref,
     sync::LazyLock,
 };
@@ -13,6 +14,17 @@ pub struct Tokeneer<M> {
     special_regex: Regex,
 }
 
+/// [`Tokeneer::encode_full`] 的结果，与 HuggingFace 的 `Encoding` 对应
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Encoding {
+    /// 编码得到的词
+    pub tokens: Vec<utok>,
+    /// 每个词在原文中的字节范围
+    pub offsets: Vec<Range<usize>>,
+    /// 每个词是否为特殊标记
+    pub special_mask: Vec<bool>,
+}
+
 enum TokenSeq {
     Single(utok),
     Multi(Box<[utok]>),
@@ -58,12 +70,97 @@ impl<M: Method> Tokeneer<M> {
         ans
     }
 
+    /// 编码文本，同时给出每个词在原文中的范围和是否为特殊标记。
+    ///
+    /// 对应 HuggingFace 的 `encode`，由于 [`Tokeneer::encode`] 已经返回词序列，为保持兼容使用这个名字。
+    /// 普通文本的范围按解码后的字节长度依次累加，限制在所属片段内，分词器在片段开头添加的空格不计入范围。
+    /// 分词器自动添加的 bos/eos 等词和特殊词标记为特殊标记，范围为片段开头或结尾的空范围。
+    /// unk、规范化改变了文本等解码结果与原文不同的词范围不精确。
+    pub fn encode_full(&self, text: &str) -> Encoding {
+        let special = self
+            .method
+            .internal_special()
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect::<HashSet<_>>();
+        // 分词器在每段文本前后自动添加的词
+        let added = self.method.encode("").into_iter().collect::<Vec<_>>();
+        let bos = self.method.encode_with_special("", true, false);
+        let n_bos = if added.starts_with(&bos) {
+            bos.len()
+        } else {
+            0
+        };
+        let n_eos = added.len() - n_bos;
+
+        let mut ans = Encoding::default();
+        let push_text = |ans: &mut Encoding, range: Range<usize>| {
+            let segment = &text[range.clone()];
+            let tokens = self.method.encode(segment).into_iter().collect::<Vec<_>>();
+            let (n_bos, n_eos) = if tokens.len() >= added.len() {
+                (n_bos, n_eos)
+            } else {
+                (0, 0)
+            };
+            let body = &tokens[n_bos..tokens.len() - n_eos];
+            let pieces = body
+                .iter()
+                .map(|&t| self.method.pre_decode(self.method.decode(t)).into_owned())
+                .collect::<Vec<_>>();
+            // 解码结果比原文长且多出开头的空格时，是分词器添加的空格，如 SPM 的 add_space_prefix
+            let decoded = pieces.concat();
+            let mut skip = usize::from(
+                decoded.len() > segment.len()
+                    && decoded.starts_with(b" ")
+                    && !segment.starts_with(' '),
+            );
+            let mut push = |t, range, is_special| {
+                ans.tokens.push(t);
+                ans.offsets.push(range);
+                ans.special_mask.push(is_special);
+            };
+            for &t in &tokens[..n_bos] {
+                push(t, range.start..range.start, true)
+            }
+            let mut pos = range.start;
+            for (&t, piece) in zip(body, &pieces) {
+                if special.contains(&t) {
+                    push(t, pos..pos, true);
+                    continue;
+                }
+                let len = piece.len() - skip.min(piece.len());
+                skip -= piece.len().min(skip);
+                let end = (pos + len).min(range.end);
+                push(t, pos..end, false);
+                pos = end
+            }
+            for &t in &tokens[tokens.len() - n_eos..] {
+                push(t, range.end..range.end, true)
+            }
+        };
+        let mut start = 0;
+        if !self.special_regex.as_str().is_empty() {
+            for m in self.special_regex.find_iter(text) {
+                push_text(&mut ans, start..m.start());
+                for &t in &*self.special[m.as_str()] {
+                    ans.tokens.push(t);
+                    ans.offsets.push(m.range());
+                    ans.special_mask.push(true);
+                }
+                start = m.end()
+            }
+        }
+        push_text(&mut ans, start..text.len());
+        ans
+    }
+
+    /// 解码为文本，不完整的 utf-8 序列替换为 U+FFFD
     pub fn decode(&self, tokens: &[utok]) -> String {
         let mut ans = Vec::new();
         for &t in tokens {
             ans.extend_from_slice(self.method.decode(t))
         }
-        String::from_utf8(ans).unwrap()
+        String::from_utf8_lossy(&self.method.pre_decode(&ans)).into_owned()
     }
 }
 
@@ -93,6 +190,13 @@ impl<M> Tokeneer<M> {
     }
 }
 
+impl Tokeneer<Gpt2Tokenizer> {
+    /// 替换预分词的正则表达式，用于 `tokenizer.ggml.pre` 不在预置规则中的模型
+    pub fn with_pretokenizer(&mut self, exprs: Vec<String>) {
+        self.method.set_pretokenize(PretokenizeKind::Custom(exprs))
+    }
+}
+
 fn build_pattern<'a>(text: impl IntoIterator<Item = &'a String>) -> Regex {
     static SPECIAL: LazyLock<HashSet<char>> = LazyLock::new(|| {
         HashSet::from([
@@ -131,8 +235,59 @@ mod test_tokoneer {
             File::open(r"F:\edged\Split-DeepSeek-R1-Distill-Qwen-1.5B-v0.0-F16.gguf").unwrap();
         let file = unsafe { Mmap::map(&file) }.unwrap();
         let gguf = GGuf::new(&file).unwrap();
-        let t = Gpt2Tokenizer::load_gguf(&gguf);
+        let t = Gpt2Tokenizer::load_gguf(&gguf).unwrap();
         let b = Tokeneer::new(t);
         println!("{:?}", b.encode("Hello my name is"));
     }
+
+    #[test]
+    fn test_with_pretokenizer() {
+        use crate::gpt2::VocabType;
+
+        let mut t = Gpt2Tokenizer::new();
+        t.vocab_type = VocabType::Bpe;
+        t.config.flags.add_bos = false;
+        for (i, piece) in ["1", "2", "3", "12", "123"].into_iter().enumerate() {
+            t.token_to_id.insert(piece.into(), i as _);
+        }
+        t.bpe_ranks.insert(("1".into(), "2".into()), 0);
+        t.bpe_ranks.insert(("12".into(), "3".into()), 1);
+
+        let mut b = Tokeneer::new(t);
+        // 默认的 qwen 规则逐个切分数字
+        assert_eq!(b.encode("123"), [0, 1, 2]);
+        b.with_pretokenizer(vec![r"\p{N}+".into()]);
+        assert_eq!(b.encode("123"), [4]);
+        b.with_pretokenizer(vec![r"\p{N}{1,2}".into()]);
+        assert_eq!(b.encode("123"), [3, 2]);
+    }
+
+    #[test]
+    fn test_encode_full() {
+        use super::Encoding;
+        use crate::{Lpe, TokenType};
+
+        let vocabs: [&[u8]; 5] = [b"<unk>", b"<s>", b"a", b"ab", b"\xe4\xb8\xad"];
+        let types = [
+            TokenType::Normal,
+            TokenType::Control,
+            TokenType::Normal,
+            TokenType::Normal,
+            TokenType::Normal,
+        ];
+        let b = Tokeneer::new(Lpe::new(vocabs, types, 0, false));
+        let text = "ab中<s>a";
+        let encoding = b.encode_full(text);
+        assert_eq!(
+            encoding,
+            Encoding {
+                tokens: vec![3, 4, 1, 2],
+                offsets: vec![0..2, 2..5, 5..8, 8..9],
+                special_mask: vec![false, false, true, false],
+            }
+        );
+        assert_eq!(encoding.tokens, b.encode(text));
+        assert_eq!(encoding.offsets.len(), encoding.tokens.len());
+        assert_eq!(encoding.special_mask.len(), encoding.tokens.len());
+    }
 }
@@ -1,6 +1,6 @@
 ﻿//! 这个模块提供对词表的预处理功能，这些功能适用于多种不同算法的分词器。
 
-use crate::utok;
+use crate::{Method, utok};
 use log::trace;
 use std::{iter::zip, pin::Pin, slice::from_ref, str::from_utf8_unchecked};
 
@@ -26,6 +26,47 @@ pub(crate) struct CollectedVocab<'s> {
     pub unk: utok,
 }
 
+/// 解析词表文件时产生的错误。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VocabError {
+    /// 第 `line` 行（从 1 开始）不是合法的 utf-8
+    InvalidUtf8 { line: usize },
+    /// json 文本在第 `pos` 字节处语法错误
+    InvalidJson { pos: usize },
+    /// 第 `index` 个词（从 0 开始）缺少字段或字段值非法
+    InvalidPiece { index: usize },
+}
+
+impl std::fmt::Display for VocabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUtf8 { line } => write!(f, "invalid utf-8 at line {line}"),
+            Self::InvalidJson { pos } => write!(f, "invalid json at byte {pos}"),
+            Self::InvalidPiece { index } => write!(f, "invalid piece at index {index}"),
+        }
+    }
+}
+
+impl std::error::Error for VocabError {}
+
+/// 两个词表按词的字节内容比较的差异，由 [`Method::diff`] 产生，序号均按升序排列
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct VocabDiff {
+    /// 只在当前词表中出现的词
+    pub only_in_self: Vec<utok>,
+    /// 只在另一个词表中出现的词
+    pub only_in_other: Vec<utok>,
+    /// 两个词表都有但序号不同的词，依次为当前词表和另一个词表中的序号
+    pub id_mismatch: Vec<(utok, utok)>,
+}
+
+impl VocabDiff {
+    /// 两个词表的内容和序号完全一致
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.id_mismatch.is_empty()
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum TokenType {
     Unknown,
@@ -38,16 +79,40 @@ pub enum TokenType {
 impl<'s> CollectedVocab<'s> {
     /// 收集词表。
     pub fn collect(
-        vocabs_: impl IntoIterator<Item = &'s [u8]>,
+        vocabs: impl IntoIterator<Item = &'s [u8]>,
         token_type: impl IntoIterator<Item = TokenType>,
         unk: utok,
     ) -> Self {
+        Self::collect_pairs(zip(vocabs, token_type), unk)
+    }
+
+    /// 收集词表，同时收集每个词的评分。
+    ///
+    /// 评分与词经过相同的收集过程，因此返回的评分总是与词表逐项对齐。
+    pub fn collect_with_scores(
+        vocabs: impl IntoIterator<Item = &'s [u8]>,
+        scores: impl IntoIterator<Item = f32>,
+        token_type: impl IntoIterator<Item = TokenType>,
+        unk: utok,
+    ) -> (Self, Vec<f32>) {
+        let mut scores_ = Vec::new();
+        let ans = Self::collect_pairs(
+            zip(zip(vocabs, scores), token_type).map(|((piece, score), tt)| {
+                scores_.push(score);
+                (piece, tt)
+            }),
+            unk,
+        );
+        (ans, scores_)
+    }
+
+    fn collect_pairs(pairs: impl IntoIterator<Item = (&'s [u8], TokenType)>, unk: utok) -> Self {
         let mut bytes = Box::new([unk; 256]);
         let mut total_len = 0;
 
         let mut vocabs = Vec::new();
         let mut special = Vec::new();
-        for (i, (piece, tt)) in zip(vocabs_, token_type).enumerate() {
+        for (i, (piece, tt)) in pairs.into_iter().enumerate() {
             let piece = match tt {
                 TokenType::Byte => {
                     trace!("find {tt:?}: {} @ {i}", unsafe {
@@ -123,6 +188,89 @@ impl CompressedVocab {
     }
 }
 
+/// 稳定的 64 位 FNV-1a 哈希，结果不随平台和编译器版本变化。
+pub(crate) struct Fingerprint(u64);
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fingerprint {
+    /// 对词表内容和特殊词计算指纹
+    pub fn vocab<M: Method + ?Sized>(method: &M) -> Self {
+        let mut ans = Self::default();
+        for (t, piece) in method.iter_vocab() {
+            ans.write_u32(t);
+            ans.write_bytes(&piece)
+        }
+        let mut special = method
+            .internal_special()
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect::<Vec<_>>();
+        special.sort_unstable();
+        ans.write_u32(special.len() as _);
+        for t in special {
+            ans.write_u32(t)
+        }
+        ans
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3)
+        }
+    }
+
+    #[inline]
+    pub fn write_u32(&mut self, val: u32) {
+        self.write(&val.to_le_bytes())
+    }
+
+    /// 写入带长度前缀的字节序列，使相邻的序列不会混淆
+    #[inline]
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as _);
+        self.write(bytes)
+    }
+
+    #[inline]
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 将词的字节序列渲染为便于阅读的字符串。
+///
+/// 与 llama.cpp 打印词表的方式一致：`U+2581` 显示为 `_`，
+/// 不可打印的字符和非法 utf-8 字节显示为 `<0xXY>`，其余合法 utf-8 内容原样输出。
+pub(crate) fn display_piece(piece: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut ans = String::with_capacity(piece.len());
+    for chunk in piece.utf8_chunks() {
+        let mut buf = [0u8; 4];
+        for c in chunk.valid().chars() {
+            match c {
+                '\u{2581}' => ans.push('_'),
+                c if c.is_control() => {
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        write!(ans, "<0x{b:02X}>").unwrap()
+                    }
+                }
+                c => ans.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            write!(ans, "<0x{b:02X}>").unwrap()
+        }
+    }
+    ans
+}
+
 const BYTES: [u8; 256] = {
     let mut bytes = [0u8; 256];
     let mut i = 0usize;